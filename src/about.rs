@@ -0,0 +1,217 @@
+use url::Url;
+
+use crate::bookmarks::Bookmarks;
+use crate::commands::COMMANDS;
+use crate::downloads::{downloads_page, Downloads};
+use crate::history::History;
+use crate::keymap::{format_keys, Action, Keymap};
+use crate::settings::{IdentitySettings, Settings, SETTING_KEYS};
+use crate::transaction::response::{create_fake_response, Response};
+use crate::transaction::tofu::KnownHosts;
+
+// The pages under about:, each with what it's for.
+pub const ABOUT_PAGES: [(&str, &str); 8] = [
+    ("blank", "An empty page"),
+    ("help", "Keys and commands"),
+    ("config", "The settings in use"),
+    ("certs", "Known server certificates and identities"),
+    ("version", "The version of armstrong"),
+    ("bookmarks", "Bookmarks"),
+    ("history", "Pages visited, newest first"),
+    ("downloads", "Files downloaded this session"),
+];
+
+// How many pages about:history lists.
+const HISTORY_PAGE_LEN: usize = 500;
+
+// Lists every action with its keys, along with the commands and the other
+// about: pages.
+pub fn help_page(keymap: &Keymap) -> String {
+    let mut page = "# Help\n\n## Keys\n".to_owned();
+    for action in Action::ALL {
+        let keys: Vec<String> = keymap
+            .keys_for(action)
+            .iter()
+            .map(|keys| format_keys(keys))
+            .collect();
+        let keys = if keys.is_empty() { "not bound".to_owned() } else { keys.join(", ") };
+        page.push_str(&format!("* {}: {} ({})\n", action.description(), keys, action.name()));
+    }
+    page.push_str("\n## Commands\n");
+    page.push_str("The command line takes these, and the name of any action above.\n");
+    for command in COMMANDS {
+        page.push_str(&format!("* {}\n", command));
+    }
+    page.push_str("\n## Pages\n");
+    for (name, description) in ABOUT_PAGES {
+        page.push_str(&format!("=> about:{} {}\n", name, description));
+    }
+    page
+}
+
+fn identities_section(identities: &[IdentitySettings]) -> String {
+    let mut section = "## Identities\n".to_owned();
+    if identities.is_empty() {
+        section.push_str("No identities are set up in config.toml.\n");
+    }
+    for identity in identities {
+        section.push_str(&format!("### {}\n", identity.name));
+        section.push_str(&format!("* Certificate: {}\n", identity.cert));
+        section.push_str(&format!("* Key: {}\n", identity.key));
+        for url in &identity.urls {
+            section.push_str(&format!("=> {} Sent to {}\n", url, url));
+        }
+    }
+    section
+}
+
+// Shows the settings in use, after the defaults and any :set commands.
+pub fn config_page(settings: &Settings) -> String {
+    let mut page = "# Config\n\n## Settings\nAny of these can be changed with :set.\n```\n".to_owned();
+    for key in SETTING_KEYS {
+        page.push_str(&format!("{} = {}\n", key, settings.get(key).unwrap_or_default()));
+    }
+    page.push_str("```\n\n## Handlers\n");
+    let mut handlers: Vec<_> = settings.handlers.iter().collect();
+    handlers.sort_by(|a, b| a.0.cmp(b.0));
+    for (pattern, handler) in handlers {
+        let terminal = if handler.terminal { ", in the terminal" } else { "" };
+        page.push_str(&format!("* {}: {}{}\n", pattern, handler.command, terminal));
+    }
+    let mut bindings: Vec<_> = settings.keys.bindings.iter().collect();
+    bindings.sort();
+    page.push_str(&format!("\n## Keys\nThe {} preset", settings.keys.preset));
+    if bindings.is_empty() {
+        page.push_str(".\n");
+    } else {
+        page.push_str(", with these rebound:\n");
+        for (action, keys) in bindings {
+            page.push_str(&format!("* {}: {}\n", action, keys.join(", ")));
+        }
+    }
+    page.push('\n');
+    page.push_str(&identities_section(&settings.identities));
+    page
+}
+
+// Lists the server certificates trusted so far and the client certificates
+// that can be sent.
+pub fn certs_page(known_hosts: &KnownHosts, identities: &[IdentitySettings]) -> String {
+    let mut page = "# Certificates\n\n## Known hosts\n".to_owned();
+    page.push_str("Servers are trusted with the first certificate they show. ");
+    page.push_str("These are the SHA-256 fingerprints kept for each.\n");
+    let mut hosts: Vec<_> = known_hosts.hosts().iter().collect();
+    hosts.sort();
+    if hosts.is_empty() {
+        page.push_str("\nNo servers have been visited yet.\n");
+    } else {
+        page.push_str("```\n");
+        for (host, fingerprint) in hosts {
+            page.push_str(&format!("{} {}\n", host, fingerprint));
+        }
+        page.push_str("```\n");
+    }
+    page.push('\n');
+    page.push_str(&identities_section(identities));
+    page
+}
+
+pub fn version_page() -> String {
+    format!("# armstrong {}\n\nA gemini browser for the terminal.\n", env!("CARGO_PKG_VERSION"))
+}
+
+pub fn bookmarks_page(bookmarks: &Bookmarks) -> String {
+    let mut page = "# Bookmarks\n\n".to_owned();
+    if bookmarks.entries().is_empty() {
+        page.push_str("Nothing has been bookmarked yet.\n");
+    }
+    for bookmark in bookmarks.entries() {
+        let title = if bookmark.title.is_empty() { &bookmark.url } else { &bookmark.title };
+        page.push_str(&format!("=> {} {}\n", bookmark.url, title));
+    }
+    page
+}
+
+pub fn history_page(history: &History) -> String {
+    let mut page = "# History\n\n".to_owned();
+    if history.entries().is_empty() {
+        page.push_str("No pages have been visited yet.\n");
+    }
+    for entry in history.entries().iter().rev().take(HISTORY_PAGE_LEN) {
+        page.push_str(&format!("=> {}\n", entry.url));
+    }
+    page
+}
+
+// What the about: pages are built from, borrowed from the browser or from
+// whatever armstrong fetch loaded.
+pub struct AboutSources<'a> {
+    pub settings: &'a Settings,
+    pub keymap: &'a Keymap,
+    pub known_hosts: &'a KnownHosts,
+    pub bookmarks: &'a Bookmarks,
+    pub history: &'a History,
+    pub downloads: &'a Downloads,
+}
+
+// The page at an about: url as a gemtext response, or a 51 if there's no
+// such page.
+pub fn about_response(url: &Url, sources: &AboutSources) -> Response {
+    let page = match url.path() {
+        "blank" => Some(String::new()),
+        "help" => Some(help_page(sources.keymap)),
+        "config" => Some(config_page(sources.settings)),
+        "certs" => Some(certs_page(sources.known_hosts, &sources.settings.identities)),
+        "version" => Some(version_page()),
+        "bookmarks" => Some(bookmarks_page(sources.bookmarks)),
+        "history" => Some(history_page(sources.history)),
+        "downloads" => Some(downloads_page(sources.downloads)),
+        _ => None,
+    };
+    page
+        .and_then(|page| Response::new(&format!("20 text/gemini\r\n{}", page)).ok())
+        .unwrap_or_else(|| create_fake_response(51, &format!("There's no page at {}.", url)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_lists_keys_from_the_keymap() {
+        let page = help_page(&Keymap::preset("vim").unwrap());
+        assert!(page.contains("* Go to a URL: o (goto)\n"));
+        assert!(page.contains("* Go to the top of the page: gg (top)\n"));
+        assert!(page.contains("=> about:certs "));
+        let page = help_page(&Keymap::preset("default").unwrap());
+        assert!(page.contains("* Copy the page source: not bound (copy_source)\n"));
+    }
+
+    #[test]
+    fn config_shows_every_setting() {
+        let page = config_page(&Settings::default());
+        assert!(page.contains("display.wrap_width = 0\n"));
+        assert!(page.contains("* audio/*: mpv --no-video %f, in the terminal\n"));
+        assert!(page.contains("The default preset.\n"));
+    }
+
+    #[test]
+    fn about_urls_are_answered_locally() {
+        let settings = Settings::default();
+        let keymap = Keymap::preset("default").unwrap();
+        let (known_hosts, bookmarks) = (KnownHosts::in_memory(), Bookmarks::in_memory());
+        let (history, downloads) = (History::in_memory(), Downloads::default());
+        let sources = AboutSources {
+            settings: &settings,
+            keymap: &keymap,
+            known_hosts: &known_hosts,
+            bookmarks: &bookmarks,
+            history: &history,
+            downloads: &downloads,
+        };
+        let response = about_response(&Url::parse("about:version").unwrap(), &sources);
+        assert_eq!((response.status, response.mimetype.as_str()), (20, "text/gemini"));
+        assert!(response.body.starts_with("# armstrong "));
+        assert_eq!(about_response(&Url::parse("about:nothing").unwrap(), &sources).status, 51);
+    }
+}
@@ -0,0 +1,126 @@
+use url::Url;
+
+// Schemes whose URLs have no "//", which are parsed as they are rather than
+// taken for a host name and port.
+const OPAQUE_SCHEMES: [&str; 2] = ["about:", "mailto:"];
+
+// Handles addresses that can't be turned into a URL.
+#[derive(Clone, Debug)]
+pub struct AddressError {
+    details: String,
+}
+
+impl std::fmt::Display for AddressError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl AddressError {
+    fn new(message: &str) -> AddressError {
+        AddressError {
+            details: message.to_owned(),
+        }
+    }
+}
+
+// Whether input reads as search terms rather than a host name, like "rust
+// borrow checker" or "gemini".
+fn is_search(input: &str) -> bool {
+    input.contains(char::is_whitespace)
+        || !(input.contains('.') || input.contains(':') || input.contains('/')
+             || input.starts_with("localhost"))
+}
+
+// Turns what was typed into the address bar into a URL. Addresses without a
+// scheme are taken to be gemini ones, and search terms are sent as the query
+// of search_url, the way a capsule asking for input would get them.
+pub fn parse_address(input: &str, search_url: &str) -> Result<Url, AddressError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(AddressError::new("No address given"));
+    }
+
+    let invalid = |e: url::ParseError| AddressError::new(&format!("Invalid URL {}: {}", input, e));
+    let opaque = OPAQUE_SCHEMES.iter().any(|scheme| input.starts_with(scheme));
+    let mut url = if input.contains("://") || opaque {
+        Url::parse(input).map_err(invalid)?
+    } else if is_search(input) {
+        if search_url.is_empty() {
+            return Err(AddressError::new(&format!(
+                        "{} isn't a URL and no search capsule is set", input)));
+        }
+        let mut url = Url::parse(search_url).map_err(|e| AddressError::new(
+                &format!("Invalid search URL {}: {}", search_url, e)))?;
+        url.set_query(Some(input));
+        url
+    } else {
+        Url::parse(&format!("gemini://{}", input)).map_err(invalid)?
+    };
+
+    if url.scheme() == "gemini" {
+        if url.host_str().unwrap_or("").is_empty() {
+            return Err(AddressError::new(&format!("Invalid URL {}: no host", input)));
+        }
+        // gemini isn't a scheme the url crate knows, so it leaves the path
+        // of gemini://host empty where it would add a / for https.
+        if url.path().is_empty() {
+            url.set_path("/");
+        }
+    }
+    Ok(url)
+}
+
+// The urls starting with input, or with input once their scheme is left
+// off, so "exa" finds gemini://example.org/. Each is listed once, in the
+// order given.
+pub fn complete_address(input: &str, urls: &[String]) -> Vec<String> {
+    let mut candidates: Vec<String> = Vec::new();
+    for url in urls {
+        let matches = url.starts_with(input)
+            || url.split_once("://").is_some_and(|(_, rest)| rest.starts_with(input));
+        if matches && !candidates.contains(url) {
+            candidates.push(url.clone());
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEARCH: &str = "gemini://search.example/search";
+
+    fn parse(input: &str) -> String {
+        parse_address(input, SEARCH).unwrap().to_string()
+    }
+
+    #[test]
+    fn addresses_become_urls() {
+        assert_eq!(parse("gemini.circumlunar.space"), "gemini://gemini.circumlunar.space/");
+        assert_eq!(parse(" localhost:1966/docs "), "gemini://localhost:1966/docs");
+        assert_eq!(parse("https://example.org/a"), "https://example.org/a");
+        assert_eq!(parse("about:blank"), "about:blank");
+        assert_eq!(parse("rust borrow checker"), format!("{}?rust%20borrow%20checker", SEARCH));
+        assert_eq!(parse("gemini"), format!("{}?gemini", SEARCH));
+
+        assert!(parse_address("", SEARCH).is_err());
+        assert!(parse_address("gemini://", SEARCH).is_err());
+        assert!(parse_address("http://exa mple.org", SEARCH).is_err());
+        assert_eq!(parse_address("two words", "").unwrap_err().to_string(),
+            "two words isn't a URL and no search capsule is set");
+    }
+
+    #[test]
+    fn completion_skips_the_scheme() {
+        let urls = vec![
+            "gemini://example.org/".to_owned(),
+            "gemini://other.org/".to_owned(),
+            "gemini://example.org/".to_owned(),
+        ];
+        assert_eq!(complete_address("exa", &urls), vec!["gemini://example.org/"]);
+        assert_eq!(complete_address("gemini://", &urls).len(), 2);
+        assert!(complete_address("nothing", &urls).is_empty());
+    }
+}
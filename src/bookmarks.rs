@@ -0,0 +1,107 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use url::Url;
+
+use crate::gemtext::{parse_gemtext, TokenKind};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bookmark {
+    pub url: String,
+    pub title: String,
+}
+
+// Saved pages, in the order they were added. When backed by a file the
+// bookmarks are kept in it as gemtext link lines, so the file can be read
+// like any other page.
+#[derive(Clone, Debug, Default)]
+pub struct Bookmarks {
+    entries: Vec<Bookmark>,
+    path: Option<PathBuf>,
+}
+
+impl Bookmarks {
+    // Bookmarks that are never written to disk.
+    pub fn in_memory() -> Bookmarks {
+        Bookmarks::default()
+    }
+
+    // Reads the bookmarks file at path. A missing or unreadable file gives
+    // no bookmarks, which will still be saved to path.
+    pub fn load(path: &Path) -> Bookmarks {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        let entries = parse_gemtext(&contents)
+            .iter()
+            .filter(|token| token.kind == TokenKind::Link)
+            .map(|token| Bookmark {
+                url: token.data.trim().to_owned(),
+                title: token.extra.trim().to_owned(),
+            })
+            .collect();
+        Bookmarks {
+            entries,
+            path: Some(path.to_path_buf()),
+        }
+    }
+
+    // Bookmarks url unless it already is. Returns whether it was added.
+    pub fn add(&mut self, url: &Url, title: &str) -> bool {
+        if self.contains(url) {
+            return false;
+        }
+        self.entries.push(Bookmark {
+            url: url.to_string(),
+            title: title.trim().to_owned(),
+        });
+        self.save();
+        true
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        self.entries.iter().any(|bookmark| bookmark.url == url.as_str())
+    }
+
+    pub fn entries(&self) -> &[Bookmark] {
+        &self.entries
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut contents = String::from("# Bookmarks\n\n");
+        for bookmark in &self.entries {
+            if bookmark.title.is_empty() {
+                contents.push_str(&format!("=> {}\n", bookmark.url));
+            } else {
+                contents.push_str(&format!("=> {} {}\n", bookmark.url, bookmark.title));
+            }
+        }
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(path, contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bookmarks_file_round_trips() {
+        let path = Path::new("/tmp/armstrong_bookmarks_test.gmi");
+        let _ = fs::remove_file(path);
+        let mut bookmarks = Bookmarks::load(path);
+        let url = Url::parse("gemini://example.org/").unwrap();
+        assert!(bookmarks.add(&url, "Example capsule"));
+        assert!(!bookmarks.add(&url, "Again"));
+
+        let reloaded = Bookmarks::load(path);
+        assert_eq!(reloaded.entries(), &[Bookmark {
+            url: "gemini://example.org/".to_owned(),
+            title: "Example capsule".to_owned(),
+        }]);
+    }
+}
@@ -0,0 +1,273 @@
+use std::path::Path;
+
+use crate::fetch::{FetchOptions, OutputMode};
+use crate::render::RenderOptions;
+
+pub const USAGE: &str = "\
+Usage: armstrong [OPTIONS] [URL...]
+       armstrong fetch [OPTIONS] URL
+       armstrong render [OPTIONS] [FILE...]
+
+Opens each URL in a tab, or the home page if none are given.
+
+Options:
+  -c, --config FILE   Read settings from FILE instead of config.toml
+  -p, --private       Don't record history, certificates or the session
+  -t, --theme NAME    Use the theme NAME this time
+  -V, --version       Print the version and exit
+  -h, --help          Print this help and exit
+
+armstrong fetch writes the response for URL to stdout without starting the
+browser. Redirects are followed, and certificates and identities are checked
+and sent as they are while browsing, and about: pages work too. It exits
+with 0 for a success, the status for any other response and 1 if there was
+no response.
+
+Fetch options:
+  -c, --config FILE   Read settings from FILE instead of config.toml
+  --raw               Write the body as it was sent (the default)
+  --header            Write only the status and META
+  --text              Write the page laid out as plain text
+  --json              Write the status, META and body as JSON
+  --trust-changed     Trust a server whose certificate has changed since it
+                      was first seen, and remember the new certificate
+
+armstrong render writes each FILE, or stdin if there are none, to stdout
+laid out as a page. Output to a terminal is styled with the theme and
+wrapped to $COLUMNS; anything else gets plain text.
+
+Render options:
+  -c, --config FILE   Read settings from FILE instead of config.toml
+  -t, --theme NAME    Use the theme NAME this time
+";
+
+// Handles command lines that can't be made sense of.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CliError {
+    details: String,
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl CliError {
+    fn new(message: &str) -> CliError {
+        CliError {
+            details: message.to_owned(),
+        }
+    }
+}
+
+// How the browser was asked to start.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    // The config file to use, empty for the usual one.
+    pub config: String,
+    pub private: bool,
+    // A theme to use instead of the one in the config file.
+    pub theme: Option<String>,
+    // Addresses to open, as typed; they're parsed once the search capsule
+    // is known.
+    pub urls: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Browse(Options),
+    Fetch(FetchOptions),
+    Render(RenderOptions),
+    Version,
+    Help,
+}
+
+// A config file given on the command line is used as it is rather than
+// created, so one that isn't there is a mistake in the arguments.
+fn check_config(config: &str) -> Result<(), CliError> {
+    if config.is_empty() || Path::new(config).is_file() {
+        Ok(())
+    } else {
+        Err(CliError::new(&format!("config file {} doesn't exist", config)))
+    }
+}
+
+fn parse_fetch_args(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let mut options = FetchOptions {
+        url: String::new(),
+        mode: OutputMode::Raw,
+        config: String::new(),
+        trust_changed: false,
+    };
+    let mut urls = Vec::new();
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name.to_owned(), Some(value.to_owned())),
+            _ => (arg.clone(), None),
+        };
+        match name.as_str() {
+            "-c" | "--config" => {
+                options.config = inline_value.or_else(|| args.next()).ok_or_else(|| {
+                    CliError::new(&format!("{} needs a value", name))
+                })?;
+            },
+            "--raw" => options.mode = OutputMode::Raw,
+            "--header" => options.mode = OutputMode::Header,
+            "--text" => options.mode = OutputMode::Text,
+            "--json" => options.mode = OutputMode::Json,
+            "--trust-changed" => options.trust_changed = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            "--" => urls.extend(args.by_ref()),
+            _ if name.starts_with('-') => {
+                return Err(CliError::new(&format!("unknown option {}", name)));
+            },
+            _ => urls.push(arg),
+        }
+    }
+    options.url = match urls.len() {
+        0 => return Err(CliError::new("fetch needs a URL")),
+        1 => urls.remove(0),
+        _ => return Err(CliError::new("fetch takes only one URL")),
+    };
+    check_config(&options.config)?;
+    Ok(Command::Fetch(options))
+}
+
+fn parse_render_args(mut args: impl Iterator<Item = String>) -> Result<Command, CliError> {
+    let mut options = RenderOptions::default();
+    while let Some(arg) = args.next() {
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name.to_owned(), Some(value.to_owned())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::new(&format!("{} needs a value", name)))
+        };
+        match name.as_str() {
+            "-c" | "--config" => options.config = value(&name)?,
+            "-t" | "--theme" => options.theme = Some(value(&name)?),
+            "-h" | "--help" => return Ok(Command::Help),
+            "--" => options.files.extend(args.by_ref()),
+            _ if name.starts_with('-') && name != "-" => {
+                return Err(CliError::new(&format!("unknown option {}", name)));
+            },
+            _ => options.files.push(arg),
+        }
+    }
+    check_config(&options.config)?;
+    Ok(Command::Render(options))
+}
+
+// Reads the arguments after the program name. Options can come before or
+// after the URLs, and "--" ends them so a URL can start with a dash. A first
+// argument of "fetch" or "render" runs that command instead of the browser.
+pub fn parse_args<I>(args: I) -> Result<Command, CliError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut options = Options::default();
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(|arg| arg.as_str()) {
+        Some("fetch") => {
+            args.next();
+            return parse_fetch_args(args);
+        },
+        Some("render") => {
+            args.next();
+            return parse_render_args(args);
+        },
+        _ => {},
+    }
+    let mut only_urls = false;
+    while let Some(arg) = args.next() {
+        if only_urls || !arg.starts_with('-') || arg == "-" {
+            options.urls.push(arg);
+            continue;
+        }
+        // Long options may be given their value as --name=value.
+        let (name, inline_value) = match arg.split_once('=') {
+            Some((name, value)) if arg.starts_with("--") => (name.to_owned(), Some(value.to_owned())),
+            _ => (arg.clone(), None),
+        };
+        let mut value = |name: &str| {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| CliError::new(&format!("{} needs a value", name)))
+        };
+        match name.as_str() {
+            "--" => only_urls = true,
+            "-c" | "--config" => options.config = value(&name)?,
+            "-t" | "--theme" => options.theme = Some(value(&name)?),
+            "-p" | "--private" => options.private = true,
+            "-V" | "--version" => return Ok(Command::Version),
+            "-h" | "--help" => return Ok(Command::Help),
+            _ => return Err(CliError::new(&format!("unknown option {}", name))),
+        }
+    }
+    check_config(&options.config)?;
+    Ok(Command::Browse(options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A config file for the tests to name, which has to exist.
+    const CONFIG: &str = "/tmp/armstrong_cli_test.toml";
+
+    fn parse(args: &[&str]) -> Result<Command, CliError> {
+        std::fs::write(CONFIG, "").unwrap();
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_and_urls_are_read() {
+        assert_eq!(parse(&[]), Ok(Command::Browse(Options::default())));
+        assert_eq!(parse(&["-p", "example.org", "--theme=light", "-c", CONFIG, "--", "-x"]),
+            Ok(Command::Browse(Options {
+                config: CONFIG.to_owned(),
+                private: true,
+                theme: Some("light".to_owned()),
+                urls: vec!["example.org".to_owned(), "-x".to_owned()],
+            })));
+        assert_eq!(parse(&["example.org", "--version"]), Ok(Command::Version));
+        assert_eq!(parse(&["-h"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        assert_eq!(parse(&["--colour"]), Err(CliError::new("unknown option --colour")));
+        assert_eq!(parse(&["--theme"]), Err(CliError::new("--theme needs a value")));
+        assert_eq!(parse(&["fetch"]), Err(CliError::new("fetch needs a URL")));
+        assert_eq!(parse(&["fetch", "a", "b"]), Err(CliError::new("fetch takes only one URL")));
+        assert_eq!(parse(&["fetch", "--theme", "a"]), Err(CliError::new("unknown option --theme")));
+        assert_eq!(parse(&["-c", "/tmp/armstrong_no_such.toml"]),
+                   Err(CliError::new("config file /tmp/armstrong_no_such.toml doesn't exist")));
+        assert!(parse(&["fetch", "-c", "/tmp", "example.org"]).is_err());
+    }
+
+    #[test]
+    fn commands_take_their_own_options() {
+        assert_eq!(parse(&["fetch", "--json", "example.org", "--config=/tmp/armstrong_cli_test.toml",
+                           "--trust-changed"]),
+            Ok(Command::Fetch(FetchOptions {
+                url: "example.org".to_owned(),
+                mode: OutputMode::Json,
+                config: CONFIG.to_owned(),
+                trust_changed: true,
+            })));
+        assert_eq!(parse(&["render", "-t", "light", "a.gmi", "-"]),
+            Ok(Command::Render(RenderOptions {
+                files: vec!["a.gmi".to_owned(), "-".to_owned()],
+                config: String::new(),
+                theme: Some("light".to_owned()),
+            })));
+        // Only the first argument picks the command.
+        assert!(matches!(parse(&["example.org", "fetch"]), Ok(Command::Browse(_))));
+    }
+}
@@ -0,0 +1,73 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// Clipboard commands tried in order, for Wayland, X11 and macOS.
+const CLIPBOARD_COMMANDS: [&[&str]; 4] = [
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard"],
+    &["xsel", "--clipboard", "--input"],
+    &["pbcopy"],
+];
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (bytes[0] as usize) << 16 | (bytes[1] as usize) << 8 | bytes[2] as usize;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i)) & 63] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn run_clipboard_command(command: &[&str], text: &str) -> bool {
+    let child = Command::new(command[0])
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => return false,
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait().map(|status| status.success()).unwrap_or(false)
+}
+
+// Copies text to the clipboard with the first clipboard command that works.
+// Without one the terminal is asked to do it with an OSC 52 sequence, which
+// also reaches the local clipboard over ssh in terminals that support it.
+pub fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    if CLIPBOARD_COMMANDS.iter().any(|command| run_clipboard_command(command, text)) {
+        return Ok(());
+    }
+    OpenOptions::new()
+        .write(true)
+        .open("/dev/tty")
+        .and_then(|mut tty| write!(tty, "\x1b]52;c;{}\x07", base64(text.as_bytes())))
+        .map_err(|e| format!("Couldn't copy to the clipboard: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_the_last_group() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"gem"), "Z2Vt");
+        assert_eq!(base64(b"gemi"), "Z2VtaQ==");
+        assert_eq!(base64(b"gemin"), "Z2VtaW4=");
+    }
+}
@@ -0,0 +1,153 @@
+use crate::address::complete_address;
+use crate::keymap::Action;
+use crate::settings::SETTING_KEYS;
+
+// Commands that take arguments. Every action can be run as a command too,
+// by its name.
+pub const COMMANDS: [&str; 6] = ["open", "tabopen", "bookmark", "set", "download", "source"];
+
+// A line typed into the command line, or read from a file by :source.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Open(String),
+    TabOpen(String),
+    // Bookmarks the given URL, or the current page.
+    Bookmark(Option<String>),
+    Set(String, String),
+    // Downloads the given URL, or the current page.
+    Download(Option<String>),
+    Source(String),
+    Action(Action),
+}
+
+// Handles lines that aren't a known command or are missing arguments.
+#[derive(Clone, Debug)]
+pub struct CommandError {
+    details: String,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl CommandError {
+    fn new(message: &str) -> CommandError {
+        CommandError {
+            details: message.to_owned(),
+        }
+    }
+}
+
+// Every name that can start a command line, for completion.
+pub fn command_names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = COMMANDS.to_vec();
+    for action in Action::ALL.iter() {
+        if !names.contains(&action.name()) {
+            names.push(action.name());
+        }
+    }
+    names
+}
+
+pub fn parse_command(line: &str) -> Result<Command, CommandError> {
+    let line = line.trim().trim_start_matches(':');
+    let (name, args) = match line.split_once(char::is_whitespace) {
+        Some((name, args)) => (name, args.trim()),
+        None => (line, ""),
+    };
+    let required = |usage: &str| {
+        if args.is_empty() {
+            Err(CommandError::new(&format!("Usage: :{} {}", name, usage)))
+        } else {
+            Ok(args.to_owned())
+        }
+    };
+    let optional = || if args.is_empty() { None } else { Some(args.to_owned()) };
+
+    let command = match name {
+        "" => return Err(CommandError::new("No command given")),
+        "open" => Command::Open(required("<url>")?),
+        "tabopen" => Command::TabOpen(required("<url>")?),
+        "bookmark" => Command::Bookmark(optional()),
+        "set" => {
+            let args = required("<setting> <value>")?;
+            match args.split_once(char::is_whitespace) {
+                Some((key, value)) => Command::Set(key.to_owned(), value.trim().to_owned()),
+                None => return Err(CommandError::new(&format!("No value given for {}", args))),
+            }
+        },
+        "download" => Command::Download(optional()),
+        "source" => Command::Source(required("<file>")?),
+        _ => match Action::from_name(name) {
+            Some(action) if args.is_empty() => Command::Action(action),
+            Some(_) => return Err(CommandError::new(&format!(":{} takes no arguments", name))),
+            None => return Err(CommandError::new(&format!("Unknown command: {}", name))),
+        },
+    };
+    Ok(command)
+}
+
+// Completes the last word of a command line. Command names complete first,
+// then URLs for the commands that take one and setting keys for :set. The
+// candidates are whole lines, closest first.
+pub fn complete(line: &str, urls: &[String]) -> Vec<String> {
+    let (name, arg) = match line.split_once(' ') {
+        Some((name, arg)) => (name, arg),
+        None => {
+            return command_names()
+                .into_iter()
+                .filter(|candidate| candidate.starts_with(line))
+                .map(|candidate| candidate.to_owned())
+                .collect();
+        },
+    };
+
+    let options: Vec<String> = match name {
+        "open" | "tabopen" | "bookmark" | "download" => complete_address(arg, urls),
+        // Only the key is completed, the value is up to the user.
+        "set" if !arg.contains(' ') => {
+            SETTING_KEYS
+                .iter()
+                .filter(|key| key.starts_with(arg))
+                .map(|key| key.to_string())
+                .collect()
+        },
+        _ => Vec::new(),
+    };
+    options.into_iter().map(|option| format!("{} {}", name, option)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_parse() {
+        assert_eq!(parse_command(":open gemini://example.org/").unwrap(),
+            Command::Open("gemini://example.org/".to_owned()));
+        assert_eq!(parse_command("set display.wrap_width  80").unwrap(),
+            Command::Set("display.wrap_width".to_owned(), "80".to_owned()));
+        assert_eq!(parse_command("bookmark").unwrap(), Command::Bookmark(None));
+        assert_eq!(parse_command("next_tab").unwrap(), Command::Action(Action::NextTab));
+        assert!(parse_command("open").is_err());
+        assert!(parse_command("set display.bidi").is_err());
+        assert!(parse_command("reload now").is_err());
+        assert_eq!(parse_command("fly").unwrap_err().to_string(), "Unknown command: fly");
+    }
+
+    #[test]
+    fn completion_depends_on_the_command() {
+        assert_eq!(complete("tab", &[]), vec!["tabopen"]);
+        let urls = vec![
+            "gemini://example.org/".to_owned(),
+            "gemini://other.org/".to_owned(),
+            "gemini://example.org/".to_owned(),
+        ];
+        assert_eq!(complete("open exa", &urls), vec!["open gemini://example.org/"]);
+        assert_eq!(complete("open gemini://", &urls).len(), 2);
+        assert_eq!(complete("set display.b", &urls), vec!["set display.bidi"]);
+        assert!(complete("set display.bidi t", &urls).is_empty());
+    }
+}
@@ -0,0 +1,295 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use url::Url;
+
+// How far along a download is.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DownloadStatus {
+    InProgress,
+    Complete,
+    // What went wrong. Whatever arrived is kept in the .part file.
+    Failed(String),
+}
+
+// A file being saved, or saved, from a URL.
+#[derive(Clone, Debug)]
+pub struct Download {
+    pub id: usize,
+    pub url: Url,
+    // Where the file ends up once it's complete.
+    pub path: PathBuf,
+    // Bytes received so far.
+    pub received: u64,
+    pub status: DownloadStatus,
+}
+
+impl Download {
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+// Where a download is written until it's complete.
+pub fn part_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".part");
+    path.with_file_name(name)
+}
+
+// Every download started this session, oldest first.
+#[derive(Clone, Debug, Default)]
+pub struct Downloads {
+    downloads: Vec<Download>,
+    next_id: usize,
+}
+
+impl Downloads {
+    // Records a new download and returns its id.
+    pub fn start(&mut self, url: &Url, path: &Path) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.downloads.push(Download {
+            id,
+            url: url.clone(),
+            path: path.to_path_buf(),
+            received: 0,
+            status: DownloadStatus::InProgress,
+        });
+        id
+    }
+
+    pub fn get(&self, id: usize) -> Option<&Download> {
+        self.downloads.iter().find(|download| download.id == id)
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Download> {
+        self.downloads.iter_mut().find(|download| download.id == id)
+    }
+
+    pub fn set_received(&mut self, id: usize, received: u64) {
+        if let Some(download) = self.get_mut(id) {
+            download.received = received;
+        }
+    }
+
+    pub fn finish(&mut self, id: usize, result: Result<u64, String>) {
+        if let Some(download) = self.get_mut(id) {
+            match result {
+                Ok(received) => {
+                    download.received = received;
+                    download.status = DownloadStatus::Complete;
+                },
+                Err(error) => download.status = DownloadStatus::Failed(error),
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: usize) -> Option<Download> {
+        let i = self.downloads.iter().position(|download| download.id == id)?;
+        Some(self.downloads.remove(i))
+    }
+
+    pub fn list(&self) -> &[Download] {
+        &self.downloads
+    }
+
+    // The number of downloads still running and the bytes they've received.
+    pub fn in_progress(&self) -> (usize, u64) {
+        self.downloads
+            .iter()
+            .filter(|download| download.status == DownloadStatus::InProgress)
+            .fold((0, 0), |(count, bytes), download| (count + 1, bytes + download.received))
+    }
+}
+
+// Formats a number of bytes the way people read them, like "1.2 KB".
+pub fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = text.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// Names the file for url after the last part of its path, or its host when
+// the path is empty. Anything that could move the file somewhere else, like
+// a / or a leading dot, is replaced.
+pub fn file_name(url: &Url) -> String {
+    let name = url.path_segments()
+        .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+        .map(percent_decode)
+        .or_else(|| url.host_str().map(|host| host.to_owned()))
+        .unwrap_or_default();
+    let name: String = name
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim_start_matches('.');
+    if name.is_empty() {
+        "download".to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+// A path in dir for a file called name that's not taken, by a finished file
+// or one still downloading, numbering it like "name (1).ext" if need be.
+pub fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let taken = |path: &Path| path.exists() || part_path(path).exists();
+    let mut path = dir.join(name);
+    let mut n = 1;
+    while taken(&path) {
+        path = dir.join(format!("{} ({}){}", stem, n, extension));
+        n += 1;
+    }
+    path
+}
+
+// Copies body into file, calling on_progress with the bytes copied so far
+// after every chunk. Returns the total copied.
+pub fn save<R, F>(body: &mut R, file: &mut File, mut on_progress: F) -> io::Result<u64>
+where
+    R: Read,
+    F: FnMut(u64),
+{
+    let mut buffer = [0; 16 * 1024];
+    let mut total = 0;
+    loop {
+        let read = body.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read])?;
+        total += read as u64;
+        on_progress(total);
+    }
+    file.flush()?;
+    Ok(total)
+}
+
+// Opens path with the desktop's default program for it.
+pub fn open_file(path: &Path) -> Result<(), String> {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    Command::new(opener)
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+        .map_err(|e| format!("Couldn't open {}: {}", path.display(), e))
+}
+
+// Writes the about:downloads page, with links to open or delete each file.
+pub fn downloads_page(downloads: &Downloads) -> String {
+    let mut page = "# Downloads\n".to_owned();
+    if downloads.list().is_empty() {
+        page.push_str("\nNothing has been downloaded yet.\n");
+    }
+    for download in downloads.list().iter().rev() {
+        let name = download.name();
+        let size = format_size(download.received as usize);
+        page.push_str(&format!("\n## {}\n", name));
+        match &download.status {
+            DownloadStatus::InProgress => {
+                page.push_str(&format!("Downloading, {} so far\n", size));
+            },
+            DownloadStatus::Complete => {
+                page.push_str(&format!("{} saved to {}\n", size, download.path.display()));
+                page.push_str(&format!("=> about:downloads?open={} Open {}\n", download.id, name));
+            },
+            DownloadStatus::Failed(error) => {
+                page.push_str(&format!("Failed after {}: {}\n", size, error));
+            },
+        }
+        page.push_str(&format!("=> {} From {}\n", download.url, download.url));
+        if download.status != DownloadStatus::InProgress {
+            page.push_str(&format!("=> about:downloads?delete={} Delete the file\n", download.id));
+        }
+    }
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn file_names_come_from_the_url() {
+        let name = |url: &str| file_name(&Url::parse(url).unwrap());
+        assert_eq!(name("gemini://example.org/files/song%20one.ogg"), "song one.ogg");
+        assert_eq!(name("gemini://example.org/files/"), "files");
+        assert_eq!(name("gemini://example.org/"), "example.org");
+        assert_eq!(name("gemini://example.org/..%2F.bashrc"), "_.bashrc");
+        assert_eq!(name("gemini://example.org/.hidden"), "hidden");
+    }
+
+    #[test]
+    fn taken_names_are_numbered() {
+        let dir = Path::new("/tmp/armstrong_unique_path_test");
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        assert_eq!(unique_path(dir, "a.txt"), dir.join("a.txt"));
+        fs::write(dir.join("a.txt"), "").unwrap();
+        fs::write(dir.join("a (1).txt.part"), "").unwrap();
+        assert_eq!(unique_path(dir, "a.txt"), dir.join("a (2).txt"));
+        assert_eq!(unique_path(dir, "README"), dir.join("README"));
+    }
+
+    #[test]
+    fn downloads_are_tracked() {
+        let url = Url::parse("gemini://example.org/a.png").unwrap();
+        let mut downloads = Downloads::default();
+        let first = downloads.start(&url, Path::new("/tmp/a.png"));
+        let second = downloads.start(&url, Path::new("/tmp/a (1).png"));
+        downloads.set_received(first, 10);
+        downloads.set_received(second, 5);
+        assert_eq!(downloads.in_progress(), (2, 15));
+        downloads.finish(first, Ok(20));
+        downloads.finish(second, Err("Connection reset".to_owned()));
+        assert_eq!(downloads.in_progress(), (0, 0));
+
+        let page = downloads_page(&downloads);
+        assert!(page.contains("=> about:downloads?open=0 Open a.png"));
+        assert!(page.contains("Failed after 5 B: Connection reset"));
+        assert!(downloads.remove(first).is_some());
+        assert!(downloads.get(first).is_none());
+    }
+}
@@ -0,0 +1,280 @@
+use std::io::{self, Write};
+
+use url::Url;
+
+use crate::about::AboutSources;
+use crate::address::parse_address;
+use crate::bookmarks::Bookmarks;
+use crate::downloads::Downloads;
+use crate::gemtext::parse_body;
+use crate::history::History;
+use crate::keymap::Keymap;
+use crate::render::{output_width, render_text};
+use crate::settings::{default_data_dir, load_keymap, load_settings, Settings};
+use crate::transaction::identity::{identity_for, load_identity};
+use crate::transaction::response::Response;
+use crate::transaction::tofu::{host_key, KnownHosts};
+use crate::transaction::visit::open;
+
+// Most redirects followed for one request, as the spec suggests.
+const MAX_REDIRECTS: usize = 5;
+
+// What armstrong fetch exits with when the request couldn't be made or
+// answered at all. Otherwise it exits with the response's status, or 0 for
+// success.
+pub const EXIT_ERROR: i32 = 1;
+
+// How armstrong fetch writes out a response.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OutputMode {
+    // The body as it was sent.
+    Raw,
+    // Only the status and META.
+    Header,
+    // The page laid out as plain text.
+    Text,
+    // An object with the status, META and body.
+    Json,
+}
+
+// What armstrong fetch was asked to do.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FetchOptions {
+    pub url: String,
+    pub mode: OutputMode,
+    // The config file to use, empty for the usual one.
+    pub config: String,
+    // Whether to trust a server whose certificate has changed since it was
+    // first seen, remembering the new one.
+    pub trust_changed: bool,
+}
+
+// Handles requests that never got a response worth writing out.
+#[derive(Clone, Debug)]
+pub struct FetchError {
+    details: String,
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl FetchError {
+    fn new(message: &str) -> FetchError {
+        FetchError {
+            details: message.to_owned(),
+        }
+    }
+}
+
+// Everything a request is made with outside the browser: the settings, the
+// certificates seen so far and what the about: pages show.
+pub struct FetchState {
+    pub settings: Settings,
+    pub keymap: Keymap,
+    pub known_hosts: KnownHosts,
+    pub bookmarks: Bookmarks,
+    pub history: History,
+    // Always empty, since downloads only last as long as the browser.
+    pub downloads: Downloads,
+}
+
+impl FetchState {
+    fn about_sources(&self) -> AboutSources<'_> {
+        AboutSources {
+            settings: &self.settings,
+            keymap: &self.keymap,
+            known_hosts: &self.known_hosts,
+            bookmarks: &self.bookmarks,
+            history: &self.history,
+            downloads: &self.downloads,
+        }
+    }
+}
+
+// Requests url the way the browser would, sending the identity configured
+// for it and checking the server's certificate against the known hosts, and
+// follows any redirects. A changed certificate is an error unless
+// trust_changed is set, when it's remembered and the request made again.
+// Returns the response with the URL it came from.
+pub fn fetch_url(url: &Url, state: &mut FetchState, trust_changed: bool)
+    -> Result<(Url, Response), FetchError> {
+    let mut url = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let identity = identity_for(&state.settings.identities, &url)
+            .map(load_identity)
+            .transpose()
+            .map_err(|e| FetchError::new(&e.to_string()))?;
+        let host = host_key(&url);
+        let stream = match open(&url, identity.as_ref(), &state.known_hosts, &state.about_sources()) {
+            Ok(stream) => stream,
+            Err(e) => match e.changed {
+                Some(fingerprint) if trust_changed => {
+                    state.known_hosts.trust(&host, &fingerprint);
+                    open(&url, identity.as_ref(), &state.known_hosts, &state.about_sources())
+                        .map_err(|e| FetchError::new(&e.to_string()))?
+                },
+                Some(_) => return Err(FetchError::new(&format!(
+                            "The certificate of {} has changed since it was first seen; \
+                            use --trust-changed to trust the new one", host))),
+                None => return Err(FetchError::new(&e.to_string())),
+            },
+        };
+        if let Some(fingerprint) = &stream.fingerprint {
+            state.known_hosts.check(&host, fingerprint);
+        }
+        if !(30..=39).contains(&stream.response.status) {
+            return Ok((url, stream.finish().response));
+        }
+        let target = url.join(stream.response.meta.trim()).map_err(|e| FetchError::new(
+                &format!("{} redirects to {}: {}", url, stream.response.meta, e)))?;
+        if target.scheme() != "gemini" {
+            return Err(FetchError::new(&format!("{} redirects to {}", url, target)));
+        }
+        url = target;
+    }
+    Err(FetchError::new(&format!("Gave up after {} redirects", MAX_REDIRECTS)))
+}
+
+// The exit status for a response: 0 for success, or else the status itself,
+// so scripts can tell the class from the first digit.
+pub fn exit_code(status: u8) -> i32 {
+    if (20..=29).contains(&status) {
+        0
+    } else {
+        status as i32
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// The response as a JSON object. Only text has a body here; anything else
+// has a null one.
+pub fn json_output(url: &Url, response: &Response) -> String {
+    let success = (20..=29).contains(&response.status);
+    let body = if success && response.is_text() {
+        json_string(&response.body)
+    } else {
+        "null".to_owned()
+    };
+    format!("{{\"url\":{},\"status\":{},\"meta\":{},\"body\":{}}}\n",
+            json_string(url.as_str()), response.status, json_string(&response.meta), body)
+}
+
+// Writes response to out as mode asks. A response that isn't a success has
+// no body to write, so only its header goes out, to err.
+fn write_response(out: &mut impl Write, err: &mut impl Write, url: &Url, response: &Response,
+                  mode: OutputMode) -> io::Result<()> {
+    let header = format!("{} {}\n", response.status, response.meta);
+    let success = (20..=29).contains(&response.status);
+    match mode {
+        OutputMode::Header => out.write_all(header.as_bytes()),
+        OutputMode::Json => out.write_all(json_output(url, response).as_bytes()),
+        _ if !success => err.write_all(header.as_bytes()),
+        OutputMode::Raw => out.write_all(&response.data),
+        OutputMode::Text if response.is_text() => {
+            let chain = parse_body(&response.mimetype, &response.body);
+            out.write_all(render_text(&chain, output_width()).as_bytes())
+        },
+        OutputMode::Text if response.mimetype.starts_with("text/") => {
+            writeln!(err, "{} is in {}, which can't be decoded", url, response.charset)
+        },
+        OutputMode::Text => {
+            writeln!(err, "{} is {}, not text", url, response.mimetype)
+        },
+    }
+}
+
+// Runs armstrong fetch and returns what to exit with.
+pub fn run_fetch(options: &FetchOptions) -> i32 {
+    let settings = match load_settings(&options.config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let url = match parse_address(&options.url, &settings.general.search_url) {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("armstrong: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let keymap = match load_keymap(&settings.keys) {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            eprintln!("{}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let mut state = FetchState {
+        settings,
+        keymap,
+        known_hosts: KnownHosts::load(&default_data_dir().join("known_hosts")),
+        bookmarks: Bookmarks::load(&default_data_dir().join("bookmarks.gmi")),
+        history: History::load(&default_data_dir().join("history")),
+        downloads: Downloads::default(),
+    };
+    let (url, response) = match fetch_url(&url, &mut state, options.trust_changed) {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            eprintln!("armstrong: {}", e);
+            return EXIT_ERROR;
+        }
+    };
+    let mode = options.mode;
+    let result = write_response(&mut io::stdout().lock(), &mut io::stderr(), &url, &response, mode);
+    match result {
+        Ok(()) if mode == OutputMode::Text && (20..=29).contains(&response.status)
+            && !response.is_text() => EXIT_ERROR,
+        // A reader that stops early, like head, isn't a failure.
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => {
+            eprintln!("armstrong: {}", e);
+            EXIT_ERROR
+        },
+        _ => exit_code(response.status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn responses_are_written_by_mode() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let response = Response::new("20 text/gemini\r\n# Hi \"there\"\n=> /a\n").unwrap();
+        let write = |response: &Response, mode| {
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            write_response(&mut out, &mut err, &url, response, mode).unwrap();
+            (String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+        };
+        assert_eq!(write(&response, OutputMode::Raw).0, "# Hi \"there\"\n=> /a\n");
+        assert_eq!(write(&response, OutputMode::Header).0, "20 text/gemini\n");
+        assert_eq!(write(&response, OutputMode::Json).0, "{\"url\":\"gemini://example.org/\",\
+\"status\":20,\"meta\":\"text/gemini\",\"body\":\"# Hi \\\"there\\\"\\n=> /a\\n\"}\n");
+
+        let response = Response::new("51 Not found\r\n").unwrap();
+        assert_eq!(write(&response, OutputMode::Raw), (String::new(), "51 Not found\n".to_owned()));
+        assert!(write(&response, OutputMode::Json).0.ends_with("\"body\":null}\n"));
+        assert_eq!((exit_code(51), exit_code(20), exit_code(10)), (51, 0, 10));
+    }
+}
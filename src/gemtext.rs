@@ -1,7 +1,6 @@
 use std::io::{BufRead, BufReader};
 
-use cursive::theme::{Effect, Style};
-use cursive::utils::markup::StyledString;
+use crate::markdown::markdown_to_gemtext;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TokenKind {
@@ -19,57 +18,71 @@ pub enum TokenKind {
 pub struct GemtextToken {
     pub kind: TokenKind,
     pub data: String,
-    pub extra: String,  // Right now this will be empty except when links are
-                        // named, when it will hold the user friendly name.
+    pub extra: String,  // Empty except for named links, when it holds the
+                        // user friendly name, and preformatted blocks, when
+                        // it holds the alt text after the opening ```.
 }
 
 impl GemtextToken {
-    pub fn styled_string(&self) -> StyledString {
-        let styled_string: StyledString;
-        let data_copy = self.data.clone();
+    // The marker drawn in front of the first line of a token. Wrapped lines
+    // are indented by the same width so the marker hangs.
+    pub fn marker(&self) -> &'static str {
+        match self.kind {
+            TokenKind::Link => "→ ",
+            TokenKind::UnorderedList => "• ",
+            TokenKind::Blockquote => "> ",
+            _ => "",
+        }
+    }
 
+    // The text shown for this token, without its marker or line ending.
+    pub fn display_text(&self) -> String {
+        let data = self.data.trim_end_matches(&['\r', '\n'][..]);
         match self.kind {
             TokenKind::Link => {
-                // Don't need the cloned data since format! Copies anyway.
-                let disp: String;
-                if self.extra.is_empty() {
-                    disp = format!("→ {}", self.data);
+                let extra = self.extra.trim_end_matches(&['\r', '\n'][..]);
+                if extra.is_empty() {
+                    data.to_owned()
                 } else {
-                    disp = format!("→ {}", self.extra);
+                    extra.to_owned()
                 }
-                styled_string = StyledString::styled(disp,
-                    Style::from(Effect::Underline));
-            },
-            TokenKind::Heading => {
-                // TODO: figure out how to combine effects.
-                // let effect = Effect::Underline & Effect::Bold;
-                styled_string = StyledString::styled(data_copy.to_uppercase(),
-                    Style::from(Effect::Bold));
-            },
-            TokenKind::SubHeading => {
-                let mut style = Style::default();
-                style.effects = Effect::Underline & Effect::Bold;
-                styled_string = StyledString::styled(data_copy,
-                    style);
-            },
-            TokenKind::SubSubHeading => {
-                styled_string = StyledString::styled(data_copy,
-                    Style::from(Effect::Bold));
-            },
-            TokenKind::PreFormattedText => {
-                styled_string = StyledString::styled(data_copy,
-                    Style::default());
             },
-            _ => {
-                styled_string = StyledString::styled(data_copy,
-                    Style::default());
-            }
+            _ => data.to_owned(),
         }
-
-        styled_string
     }
 }
 
+// A heading on a page, as listed in its table of contents.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutlineEntry {
+    // Index of the heading's token in the chain.
+    pub token: usize,
+    // 1 for headings, 2 for sub headings and 3 for sub sub headings.
+    pub level: usize,
+    pub title: String,
+}
+
+// Lists the headings of a page in the order they appear.
+pub fn outline(chain: &[GemtextToken]) -> Vec<OutlineEntry> {
+    chain
+        .iter()
+        .enumerate()
+        .filter_map(|(i, token)| {
+            let level = match token.kind {
+                TokenKind::Heading => 1,
+                TokenKind::SubHeading => 2,
+                TokenKind::SubSubHeading => 3,
+                _ => return None,
+            };
+            Some(OutlineEntry {
+                token: i,
+                level,
+                title: token.display_text().trim().to_owned(),
+            })
+        })
+        .collect()
+}
+
 // Returns a Vec<&str> from a given str with newline and linefeed bytes
 // maintained.
 fn split_keep_crlf(raw_text: &str) -> Vec<String> {
@@ -95,28 +108,30 @@ pub fn parse_gemtext(raw_text: &str) -> Vec<GemtextToken> {
     let raw_text_lines: Vec<String> = split_keep_crlf(raw_text);
     let mut current_pft_state: bool = false;
     let mut pft_block = String::new();
-    let mut _pft_alt_text: &str = "";
+    let mut pft_alt_text = String::new();
 
     for line in raw_text_lines {
-        let mut mode: TokenKind;
         let text_tokens: Vec<&str> = line.splitn(3, ' ').collect();
 
         if !current_pft_state {
-            match text_tokens[0] {
-                "=>"  => { mode = TokenKind::Link; },
-                "*"   => { mode = TokenKind::UnorderedList; },
-                ">"   => { mode = TokenKind::Blockquote; },
-                "###" => { mode = TokenKind::SubSubHeading; },
-                "##"  => { mode = TokenKind::SubHeading; },
-                "#"   => { mode = TokenKind::Heading; },
-                _     => {
-                    mode = TokenKind::Text;
-                },
-            }
-            if text_tokens[0].starts_with("```") {
-                mode = TokenKind::PreFormattedText;
+            if let Some(alt_text) = line.strip_prefix("```") {
+                // Anything after the backticks on the opening line is the
+                // block's alt text.
+                current_pft_state = true;
+                pft_alt_text = alt_text.trim().to_owned();
+                continue;
             }
 
+            let mode = match text_tokens[0] {
+                "=>"  => TokenKind::Link,
+                "*"   => TokenKind::UnorderedList,
+                ">"   => TokenKind::Blockquote,
+                "###" => TokenKind::SubSubHeading,
+                "##"  => TokenKind::SubHeading,
+                "#"   => TokenKind::Heading,
+                _     => TokenKind::Text,
+            };
+
             match text_tokens.len() {
                 3 => {
                     if mode == TokenKind::Link {
@@ -148,28 +163,28 @@ pub fn parse_gemtext(raw_text: &str) -> Vec<GemtextToken> {
                     }
                 },
                 2 => {
-                    if mode == TokenKind::PreFormattedText && !current_pft_state {
-                        current_pft_state = true;
-                        _pft_alt_text = text_tokens[1];
-                    }
-                    else {
+                    if mode == TokenKind::Text {
                         gemtext_token_chain.push(GemtextToken {
                             kind: mode,
-                            data: text_tokens[1].to_owned(),
+                            data: format!("{} {}",
+                                text_tokens[0],
+                                text_tokens[1]),
                             extra: "".to_owned(),
                         });
-                    }
-                },
-                _ => {
-                    if mode == TokenKind::PreFormattedText && !current_pft_state {
-                        current_pft_state = true;
                     } else {
                         gemtext_token_chain.push(GemtextToken {
                             kind: mode,
-                            data: text_tokens[0].to_owned(),
+                            data: text_tokens[1].to_owned(),
                             extra: "".to_owned(),
                         });
                     }
+                },
+                _ => {
+                    gemtext_token_chain.push(GemtextToken {
+                        kind: mode,
+                        data: text_tokens[0].to_owned(),
+                        extra: "".to_owned(),
+                    });
                 }
             }
         } else {
@@ -177,11 +192,10 @@ pub fn parse_gemtext(raw_text: &str) -> Vec<GemtextToken> {
                 let pft_block_copy = pft_block.clone();
                 pft_block.clear();
                 current_pft_state = false;
-                // TODO: Support PFT alt text.
                 gemtext_token_chain.push(GemtextToken {
                     kind: TokenKind::PreFormattedText,
                     data: pft_block_copy,
-                    extra: "".to_owned(),
+                    extra: std::mem::take(&mut pft_alt_text),
                 });
             } else {
                 pft_block.push_str(&line);
@@ -189,9 +203,34 @@ pub fn parse_gemtext(raw_text: &str) -> Vec<GemtextToken> {
         }
     }
 
+    // A block left open at the end of the page still gets shown.
+    if current_pft_state && !pft_block.is_empty() {
+        gemtext_token_chain.push(GemtextToken {
+            kind: TokenKind::PreFormattedText,
+            data: pft_block,
+            extra: pft_alt_text,
+        });
+    }
+
     gemtext_token_chain
 }
 
+// Turns the body of a text response into a page by its MIME type. Gemtext is
+// parsed, Markdown is converted to gemtext first and any other text is shown
+// as it is, in a single preformatted block, so lines that happen to start
+// with => or # aren't taken for links or headings.
+pub fn parse_body(mimetype: &str, body: &str) -> Vec<GemtextToken> {
+    match mimetype.to_ascii_lowercase().as_str() {
+        "text/gemini" => parse_gemtext(body),
+        "text/markdown" => parse_gemtext(&markdown_to_gemtext(body)),
+        _ => vec![GemtextToken {
+            kind: TokenKind::PreFormattedText,
+            data: body.to_owned(),
+            extra: "".to_owned(),
+        }],
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -205,6 +244,14 @@ mod tests {
         assert_eq!(parsed[0].data, text);
     }
 
+    #[test]
+    fn parser_keeps_both_words_of_short_text() {
+        let parsed: Vec<GemtextToken> = parse_gemtext("Hello world");
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].kind, TokenKind::Text);
+        assert_eq!(parsed[0].data, "Hello world");
+    }
+
     #[test]
     fn parser_handles_links() {
         let raw_text = "=> www.example.com";
@@ -280,4 +327,42 @@ mod tests {
         assert_eq!(parsed[0].kind, TokenKind::PreFormattedText);
         assert_eq!(parsed[0].data, line);
     }
+
+    #[test]
+    fn parser_keeps_pft_alt_text() {
+        let raw_text =
+            "```rust example\n\
+            fn main() {}\n\
+            ```\n\
+            ```sh\n\
+            ls\n\
+            ```";
+        let parsed: Vec<GemtextToken> = parse_gemtext(raw_text);
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].extra, "rust example");
+        assert_eq!(parsed[0].data, "fn main() {}\n");
+        assert_eq!(parsed[1].extra, "sh");
+    }
+
+    #[test]
+    fn outline_lists_headings() {
+        let raw_text = "# Title\nText\n## Part\n=> link\n### Detail\n";
+        let entries = outline(&parse_gemtext(raw_text));
+        let summary: Vec<(usize, usize, &str)> = entries
+            .iter()
+            .map(|e| (e.token, e.level, e.title.as_str()))
+            .collect();
+        assert_eq!(summary, vec![(0, 1, "Title"), (2, 2, "Part"), (4, 3, "Detail")]);
+    }
+
+    #[test]
+    fn plain_text_is_shown_as_it_is() {
+        let body = "# Not a heading\n=> not-a-link\n";
+        let page = parse_body("text/plain", body);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].kind, TokenKind::PreFormattedText);
+        assert_eq!(page[0].data, body);
+        assert_eq!(parse_body("text/gemini", body)[0].kind, TokenKind::Heading);
+        assert_eq!(parse_body("Text/Gemini", body)[1].kind, TokenKind::Link);
+    }
 }
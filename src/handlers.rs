@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::downloads::unique_path;
+use crate::settings::HandlerSettings;
+
+// Whether mimetype is covered by pattern, which is either a whole MIME type
+// like application/pdf, a family like image/*, or * for anything.
+pub fn mime_matches(pattern: &str, mimetype: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some(family) => mimetype
+            .split_once('/')
+            .map(|(kind, _)| kind.eq_ignore_ascii_case(family))
+            .unwrap_or(false),
+        None => pattern == "*" || pattern.eq_ignore_ascii_case(mimetype),
+    }
+}
+
+// The handler for mimetype. An exact match wins over a family like image/*,
+// which wins over *.
+pub fn handler_for<'a>(handlers: &'a HashMap<String, HandlerSettings>, mimetype: &str)
+    -> Option<&'a HandlerSettings> {
+    handlers
+        .iter()
+        .filter(|(pattern, _)| mime_matches(pattern, mimetype))
+        .max_by_key(|(pattern, _)| match pattern.as_str() {
+            "*" => 0,
+            pattern if pattern.ends_with("/*") => 1,
+            _ => 2,
+        })
+        .map(|(_, handler)| handler)
+}
+
+// Splits a command into words the way a shell would, so an argument with
+// spaces can be put in single or double quotes, or have them escaped with a
+// backslash. Nothing else a shell does, like expanding variables, is done.
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                let word = word.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some(next @ ('"' | '\\')) => word.push(next),
+                    Some(next) => {
+                        word.push('\\');
+                        word.push(next);
+                    },
+                    None => word.push('\\'),
+                }
+            },
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            },
+            (None, '\\') => {
+                let word = word.get_or_insert_with(String::new);
+                word.extend(chars.next());
+            },
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    words
+}
+
+// Splits command into the program and its arguments, putting path in place
+// of %f, or on the end when there's no %f.
+pub fn handler_args(command: &str, path: &Path) -> Vec<String> {
+    let path = path.to_string_lossy();
+    let mut args = split_command(command);
+    if args.iter().any(|arg| arg.contains("%f")) {
+        for arg in args.iter_mut() {
+            *arg = arg.replace("%f", &path);
+        }
+    } else {
+        args.push(path.into_owned());
+    }
+    args
+}
+
+// The directory bodies handed to handlers are kept in, once it's been made.
+static TEMP_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+// How many random names to try for the temporary directory before giving up.
+const TEMP_DIR_ATTEMPTS: usize = 8;
+
+fn random_name() -> io::Result<String> {
+    let mut bytes = [0; 8];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| io::Error::other("Couldn't pick a random name"))?;
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+// Where bodies handed to handlers are kept: a directory only we can use,
+// made under a random name the first time it's needed. Making it fails if
+// the name is taken, so nobody else can have put it there first. It's
+// removed when armstrong quits, since programs like xdg-open return before
+// they've read the file.
+fn temp_dir() -> io::Result<PathBuf> {
+    let mut temp_dir = TEMP_DIR.lock().map_err(|_| io::Error::other("Temporary directory lost"))?;
+    if let Some(dir) = temp_dir.as_ref() {
+        return Ok(dir.clone());
+    }
+    for _ in 0..TEMP_DIR_ATTEMPTS {
+        let dir = env::temp_dir().join(format!("armstrong-{}", random_name()?));
+        match DirBuilder::new().mode(0o700).create(&dir) {
+            Ok(()) => {
+                *temp_dir = Some(dir.clone());
+                return Ok(dir);
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(io::ErrorKind::AlreadyExists, "No free name for a temporary directory"))
+}
+
+// A free path for a file called name in the temporary directory.
+pub fn temp_path(name: &str) -> io::Result<PathBuf> {
+    Ok(unique_path(&temp_dir()?, name))
+}
+
+pub fn remove_temp_files() {
+    if let Ok(Some(dir)) = TEMP_DIR.lock().map(|mut dir| dir.take()) {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+fn spawn(args: &[String], stdio: fn() -> Stdio) -> Result<Child, String> {
+    let (program, args) = args.split_first().ok_or("No command given")?;
+    Command::new(program)
+        .args(args)
+        .stdin(stdio())
+        .stdout(stdio())
+        .stderr(stdio())
+        .spawn()
+        .map_err(|e| format!("Couldn't run {}: {}", program, e))
+}
+
+// Starts a program that opens its own window, leaving it to run on its own.
+pub fn spawn_handler(args: &[String]) -> Result<(), String> {
+    let mut child = spawn(args, Stdio::null)?;
+    // Reaps the child once it's done.
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+fn stty(args: &[&str]) -> Option<String> {
+    let tty = File::open("/dev/tty").ok()?;
+    let output = Command::new("stty").args(args).stdin(tty).output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn write_to_tty(sequence: &str) {
+    if let Ok(mut tty) = OpenOptions::new().write(true).open("/dev/tty") {
+        let _ = tty.write_all(sequence.as_bytes());
+    }
+}
+
+// Runs a program that draws in the terminal, handing the terminal over to it
+// until it exits and then putting it back the way the tui had it. The tui
+// has to be redrawn afterwards.
+//
+// This only knows how cursive's ncurses backend, the one armstrong is built
+// with, leaves the terminal: on the alternate screen, with application cursor
+// keys and keypad, xterm style button-event mouse reports and raw mode, which
+// stty saves and restores. Other backends set the terminal up differently
+// and would need their own version of this.
+pub fn run_in_terminal(args: &[String]) -> Result<(), String> {
+    let saved = stty(&["-g"]);
+    // Leaves the alternate screen and stops mouse reports.
+    write_to_tty("\x1b[?1002l\x1b[?1049l");
+    stty(&["sane"]);
+    let result = spawn(args, Stdio::inherit).and_then(|mut child| {
+        child.wait().map(|_| ()).map_err(|e| e.to_string())
+    });
+    if let Some(saved) = saved {
+        stty(&[&saved]);
+    }
+    // Back to the alternate screen, with application keys and mouse reports
+    // as before.
+    write_to_tty("\x1b[?1049h\x1b[?1h\x1b=\x1b[?1002h");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_closest_handler_wins() {
+        let handler = |command: &str| HandlerSettings {
+            command: command.to_owned(),
+            terminal: false,
+        };
+        let mut handlers = HashMap::new();
+        handlers.insert("*".to_owned(), handler("xdg-open"));
+        handlers.insert("image/*".to_owned(), handler("feh"));
+        handlers.insert("image/gif".to_owned(), handler("gifview"));
+        let command = |mimetype| handler_for(&handlers, mimetype).map(|h| h.command.as_str());
+        assert_eq!(command("image/gif"), Some("gifview"));
+        assert_eq!(command("image/PNG"), Some("feh"));
+        assert_eq!(command("application/pdf"), Some("xdg-open"));
+        assert!(handler_for(&HashMap::new(), "application/pdf").is_none());
+    }
+
+    #[test]
+    fn the_file_replaces_the_placeholder() {
+        let path = Path::new("/tmp/a.ogg");
+        assert_eq!(handler_args("mpv --no-video %f", path), ["mpv", "--no-video", "/tmp/a.ogg"]);
+        assert_eq!(handler_args("xdg-open", path), ["xdg-open", "/tmp/a.ogg"]);
+        assert_eq!(handler_args("cp %f %f.bak", path), ["cp", "/tmp/a.ogg", "/tmp/a.ogg.bak"]);
+    }
+
+    #[test]
+    fn quoted_arguments_keep_their_spaces() {
+        let path = Path::new("/tmp/my file.ogg");
+        assert_eq!(handler_args("mpv --title='a b' %f", path),
+                   ["mpv", "--title=a b", "/tmp/my file.ogg"]);
+        assert_eq!(handler_args(r#""/opt/My Player" -x ''"#, path),
+                   ["/opt/My Player", "-x", "", "/tmp/my file.ogg"]);
+        assert_eq!(handler_args(r"my\ player", path), ["my player", "/tmp/my file.ogg"]);
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::OnceLock;
+
+use syntect::easy::ScopeRegionIterator;
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+
+// The kinds of code we color. Finer grained scopes from the syntax
+// definitions are folded into one of these so they map onto a small palette.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HighlightKind {
+    Keyword,
+    String,
+    Comment,
+    Constant,
+    Function,
+    Type,
+}
+
+// A highlighted byte range of a line.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HighlightSpan {
+    pub start: usize,
+    pub end: usize,
+    pub kind: HighlightKind,
+}
+
+static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+
+fn syntax_set() -> &'static SyntaxSet {
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+// Looks up the language named by the first word of a preformatted block's
+// alt text, e.g. "rust" or "sh" but also file extensions like "py".
+fn syntax_for_alt_text(alt_text: &str) -> Option<&'static SyntaxReference> {
+    let token = alt_text.split_whitespace().next()?;
+    let syntax = syntax_set().find_syntax_by_token(token)?;
+    if syntax.name == "Plain Text" {
+        return None;
+    }
+    Some(syntax)
+}
+
+// Folds the innermost scope we know about into a HighlightKind.
+fn classify(stack: &ScopeStack) -> Option<HighlightKind> {
+    for scope in stack.as_slice().iter().rev() {
+        let name = scope.build_string();
+        let kind = if name.starts_with("comment") {
+            HighlightKind::Comment
+        } else if name.starts_with("string") {
+            HighlightKind::String
+        } else if name.starts_with("constant") {
+            HighlightKind::Constant
+        } else if name.starts_with("keyword") || name.starts_with("storage") {
+            HighlightKind::Keyword
+        } else if name.starts_with("entity.name.type")
+            || name.starts_with("support.type")
+            || name.starts_with("support.class") {
+            HighlightKind::Type
+        } else if name.starts_with("entity.name.function")
+            || name.starts_with("support.function")
+            || name.starts_with("variable.function") {
+            HighlightKind::Function
+        } else {
+            continue;
+        };
+        return Some(kind);
+    }
+    None
+}
+
+// Highlights the lines of a preformatted block. Returns None when the alt
+// text doesn't name a language we know, in which case the block should be
+// shown as plain text.
+pub fn highlight_lines(alt_text: &str, lines: &[String])
+    -> Option<Vec<Vec<HighlightSpan>>> {
+    let syntax = syntax_for_alt_text(alt_text)?;
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+    let mut highlighted = Vec::new();
+
+    for line in lines {
+        // The bundled syntaxes expect every line to end in a newline.
+        let line = format!("{}\n", line);
+        let ops = state.parse_line(&line, syntax_set()).ok()?;
+        let mut spans: Vec<HighlightSpan> = Vec::new();
+        let mut offset = 0;
+        for (region, op) in ScopeRegionIterator::new(&ops, &line) {
+            if stack.apply(op).is_err() {
+                return None;
+            }
+            let start = offset;
+            offset += region.len();
+            let end = offset.min(line.len() - 1);
+            if start >= end {
+                continue;
+            }
+            if let Some(kind) = classify(&stack) {
+                match spans.last_mut() {
+                    Some(last) if last.kind == kind && last.end == start => {
+                        last.end = end;
+                    },
+                    _ => spans.push(HighlightSpan { start, end, kind }),
+                }
+            }
+        }
+        highlighted.push(spans);
+    }
+
+    Some(highlighted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_languages_are_highlighted() {
+        let lines = vec!["fn main() { // hi".to_owned()];
+        let spans = highlight_lines("rust", &lines).unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].contains(&HighlightSpan {
+            start: 0,
+            end: 2,
+            kind: HighlightKind::Keyword,
+        }));
+        let comment = spans[0].last().unwrap();
+        assert_eq!(comment.kind, HighlightKind::Comment);
+        assert_eq!(&lines[0][comment.start..comment.end], "// hi");
+    }
+
+    #[test]
+    fn unknown_alt_text_falls_back_to_plain_text() {
+        let lines = vec!["  /\\_/\\".to_owned()];
+        assert!(highlight_lines("", &lines).is_none());
+        assert!(highlight_lines("a cat drawn in ascii", &lines).is_none());
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use url::Url;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryEntry {
+    // Seconds since the unix epoch.
+    pub visited_at: u64,
+    pub url: String,
+}
+
+// Every page that's been visited, oldest first. When backed by a file each
+// visit is appended to it as a "<visited_at>\t<url>" line.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    visited: HashSet<String>,
+    path: Option<PathBuf>,
+}
+
+// Fragments point inside a page, so they don't make it a different page.
+fn history_key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.to_string()
+}
+
+impl History {
+    // A history that is never written to disk.
+    pub fn in_memory() -> History {
+        History::default()
+    }
+
+    // Reads the history file at path. A missing or unreadable file gives an
+    // empty history that will still be saved to path.
+    pub fn load(path: &Path) -> History {
+        let mut history = History {
+            path: Some(path.to_path_buf()),
+            ..Default::default()
+        };
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        for line in contents.lines() {
+            if let Some((visited_at, url)) = line.split_once('\t') {
+                history.visited.insert(url.to_owned());
+                history.entries.push(HistoryEntry {
+                    visited_at: visited_at.parse().unwrap_or(0),
+                    url: url.to_owned(),
+                });
+            }
+        }
+        history
+    }
+
+    pub fn add(&mut self, url: &Url) {
+        let visited_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let entry = HistoryEntry {
+            visited_at,
+            url: history_key(url),
+        };
+
+        if let Some(path) = &self.path {
+            if let Some(parent) = path.parent() {
+                let _ = fs::create_dir_all(parent);
+            }
+            // Losing a history entry isn't worth interrupting browsing for.
+            if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+                let _ = writeln!(file, "{}\t{}", entry.visited_at, entry.url);
+            }
+        }
+        self.visited.insert(entry.url.clone());
+        self.entries.push(entry);
+    }
+
+    pub fn is_visited(&self, url: &Url) -> bool {
+        self.visited.contains(&history_key(url))
+    }
+
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn visits_are_remembered_without_fragments() {
+        let mut history = History::in_memory();
+        let url = Url::parse("gemini://example.org/page.gmi#top").unwrap();
+        history.add(&url);
+        let other = Url::parse("gemini://example.org/page.gmi").unwrap();
+        assert!(history.is_visited(&other));
+        assert!(!history.is_visited(&Url::parse("gemini://example.org/").unwrap()));
+    }
+
+    #[test]
+    fn history_file_round_trips() {
+        let path = Path::new("/tmp/armstrong_history_test");
+        let _ = fs::remove_file(path);
+        let mut history = History::load(path);
+        history.add(&Url::parse("gemini://example.org/").unwrap());
+        let reloaded = History::load(path);
+        assert_eq!(reloaded.entries().len(), 1);
+        assert_eq!(reloaded.entries()[0].url, "gemini://example.org/");
+        assert!(reloaded.is_visited(&Url::parse("gemini://example.org/").unwrap()));
+    }
+}
@@ -0,0 +1,154 @@
+use std::env;
+
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+// The image types that can be shown in the page.
+pub const IMAGE_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/jpg", "image/gif"];
+
+// Pixels less opaque than this are left out, so the page shows through.
+const MIN_ALPHA: u8 = 128;
+
+pub fn is_image(mimetype: &str) -> bool {
+    IMAGE_TYPES.iter().any(|image| image.eq_ignore_ascii_case(mimetype))
+}
+
+// Decodes a PNG, JPEG or GIF. Only the first frame of an animation is kept.
+pub fn decode_image(data: &[u8]) -> Result<RgbaImage, String> {
+    image::load_from_memory(data)
+        .map(|image| image.to_rgba8())
+        .map_err(|e| format!("Couldn't decode the image: {}", e))
+}
+
+// How many colors the terminal can show.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ColorDepth {
+    TrueColor,
+    Palette256,
+}
+
+impl ColorDepth {
+    // Reads the display.image_colors setting: truecolor, 256 or auto, which
+    // goes by $COLORTERM the way most terminal programs do.
+    pub fn from_setting(setting: &str) -> ColorDepth {
+        match setting {
+            "truecolor" => ColorDepth::TrueColor,
+            "256" => ColorDepth::Palette256,
+            _ => match env::var("COLORTERM") {
+                Ok(term) if term == "truecolor" || term == "24bit" => ColorDepth::TrueColor,
+                _ => ColorDepth::Palette256,
+            },
+        }
+    }
+}
+
+// One character cell of an image, drawn as an upper half block in the top
+// pixel's color over the bottom pixel's. None is a transparent pixel.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HalfBlock {
+    pub top: Option<[u8; 3]>,
+    pub bottom: Option<[u8; 3]>,
+}
+
+fn pixel(image: &RgbaImage, x: u32, y: u32) -> Option<[u8; 3]> {
+    if y >= image.height() {
+        return None;
+    }
+    let [r, g, b, a] = image.get_pixel(x, y).0;
+    if a < MIN_ALPHA {
+        None
+    } else {
+        Some([r, g, b])
+    }
+}
+
+// Scales image down to at most width cells across, never up, and turns it
+// into rows of half blocks. Each cell covers two pixels stacked, which keeps
+// the pixels about square in most terminal fonts.
+pub fn image_rows(image: &RgbaImage, width: usize) -> Vec<Vec<HalfBlock>> {
+    if image.width() == 0 || image.height() == 0 || width == 0 {
+        return Vec::new();
+    }
+    let columns = image.width().min(width as u32);
+    let height = (image.height() as u64 * columns as u64 / image.width() as u64).max(1) as u32;
+    let scaled = if columns == image.width() {
+        image.clone()
+    } else {
+        imageops::resize(image, columns, height, FilterType::Triangle)
+    };
+    (0..scaled.height())
+        .step_by(2)
+        .map(|y| {
+            (0..scaled.width())
+                .map(|x| HalfBlock {
+                    top: pixel(&scaled, x, y),
+                    bottom: pixel(&scaled, x, y + 1),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// The closest color in the xterm 256 color palette, from its 6x6x6 cube or
+// its ramp of grays.
+pub fn to_256_colors([r, g, b]: [u8; 3]) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let nearest_level = |c: u8| {
+        (0..LEVELS.len())
+            .min_by_key(|&i| (LEVELS[i] as i32 - c as i32).abs())
+            .unwrap_or(0)
+    };
+    let distance = |[r2, g2, b2]: [u8; 3]| {
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(r, r2) + d(g, g2) + d(b, b2)
+    };
+
+    let (ri, gi, bi) = (nearest_level(r), nearest_level(g), nearest_level(b));
+    let cube = [LEVELS[ri], LEVELS[gi], LEVELS[bi]];
+    let cube_index = 16 + 36 * ri + 6 * gi + bi;
+
+    let average = (r as usize + g as usize + b as usize) / 3;
+    let gray_index = (average.saturating_sub(3) / 10).min(23);
+    let level = 8 + 10 * gray_index as u8;
+    if distance([level, level, level]) < distance(cube) {
+        (232 + gray_index) as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn images_shrink_to_fit_and_keep_their_shape() {
+        let image = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+        let rows = image_rows(&image, 10);
+        assert_eq!(rows.len(), 3);
+        assert!(rows.iter().all(|row| row.len() == 10));
+        assert_eq!(rows[0][0].top, Some([255, 0, 0]));
+        // An odd number of pixel rows leaves the last bottom half empty.
+        assert_eq!(rows[2][0].bottom, None);
+
+        let small = RgbaImage::from_pixel(4, 4, Rgba([0, 0, 0, 0]));
+        let rows = image_rows(&small, 80);
+        assert_eq!((rows.len(), rows[0].len()), (2, 4));
+        assert_eq!(rows[0][0], HalfBlock { top: None, bottom: None });
+    }
+
+    #[test]
+    fn colors_map_to_the_palette() {
+        assert_eq!(to_256_colors([0, 0, 0]), 16);
+        assert_eq!(to_256_colors([255, 0, 0]), 196);
+        assert_eq!(to_256_colors([255, 255, 255]), 231);
+        assert_eq!(to_256_colors([128, 128, 128]), 244);
+    }
+
+    #[test]
+    fn only_decodable_image_types_are_images() {
+        assert!(is_image("IMAGE/PNG"));
+        assert!(!is_image("image/svg+xml"));
+    }
+}
@@ -0,0 +1,568 @@
+use std::collections::HashMap;
+use std::fmt;
+
+pub const PRESETS: [&str; 3] = ["default", "vim", "emacs"];
+
+// Keys that don't type a character.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum NamedKey {
+    Esc,
+    Enter,
+    Tab,
+    Backspace,
+    Delete,
+    Insert,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+}
+
+const NAMED_KEYS: [(&str, NamedKey); 14] = [
+    ("Esc", NamedKey::Esc),
+    ("Enter", NamedKey::Enter),
+    ("Tab", NamedKey::Tab),
+    ("BS", NamedKey::Backspace),
+    ("Del", NamedKey::Delete),
+    ("Insert", NamedKey::Insert),
+    ("Up", NamedKey::Up),
+    ("Down", NamedKey::Down),
+    ("Left", NamedKey::Left),
+    ("Right", NamedKey::Right),
+    ("Home", NamedKey::Home),
+    ("End", NamedKey::End),
+    ("PageUp", NamedKey::PageUp),
+    ("PageDown", NamedKey::PageDown),
+];
+
+// A single key press, written in config.toml the way vim writes keys: "g",
+// "<Space>", "<C-f>" for control, "<M-x>" for alt and "<S-Tab>" for shift
+// with a named key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum KeyPress {
+    Char(char),
+    Ctrl(char),
+    Alt(char),
+    Key(NamedKey),
+    Shift(NamedKey),
+}
+
+impl fmt::Display for KeyPress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = |key: &NamedKey| {
+            NAMED_KEYS.iter().find(|(_, k)| k == key).map(|(n, _)| *n).unwrap_or("?")
+        };
+        match self {
+            KeyPress::Char(' ') => write!(f, "<Space>"),
+            KeyPress::Char('<') => write!(f, "<lt>"),
+            KeyPress::Char(c) => write!(f, "{}", c),
+            KeyPress::Ctrl(c) => write!(f, "<C-{}>", c),
+            KeyPress::Alt(c) => write!(f, "<M-{}>", c),
+            KeyPress::Key(key) => write!(f, "<{}>", name(key)),
+            KeyPress::Shift(key) => write!(f, "<S-{}>", name(key)),
+        }
+    }
+}
+
+// Parses what's between the angle brackets of a special key.
+fn parse_special(name: &str) -> Option<KeyPress> {
+    let single = |s: &str| {
+        let mut chars = s.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ if s.eq_ignore_ascii_case("lt") => Some('<'),
+            _ if s.eq_ignore_ascii_case("space") => Some(' '),
+            _ => None,
+        }
+    };
+    let named = |s: &str| {
+        NAMED_KEYS.iter().find(|(n, _)| n.eq_ignore_ascii_case(s)).map(|(_, k)| *k)
+    };
+
+    if let Some(rest) = name.strip_prefix("C-") {
+        return single(rest).map(|c| KeyPress::Ctrl(c.to_ascii_lowercase()));
+    }
+    if let Some(rest) = name.strip_prefix("M-").or_else(|| name.strip_prefix("A-")) {
+        return single(rest).map(KeyPress::Alt);
+    }
+    if let Some(rest) = name.strip_prefix("S-") {
+        return named(rest).map(KeyPress::Shift);
+    }
+    match named(name) {
+        Some(key) => Some(KeyPress::Key(key)),
+        None if name.len() > 1 => single(name).map(KeyPress::Char),
+        None => None,
+    }
+}
+
+// Parses a key sequence such as "gg" or "<C-x><C-f>".
+pub fn parse_keys(keys: &str) -> Result<Vec<KeyPress>, String> {
+    let mut sequence = Vec::new();
+    let mut rest = keys;
+    while let Some(c) = rest.chars().next() {
+        // A "<" without a name and ">" after it is just the key itself.
+        let end = rest.get(2..).and_then(|s| s.find('>')).map(|i| i + 2);
+        if let (Some(mut end), '<') = (end, c) {
+            // "<M->>" is alt and ">".
+            if rest[..end].ends_with('-') && rest[end + 1..].starts_with('>') {
+                end += 1;
+            }
+            let special = &rest[1..end];
+            match parse_special(special) {
+                Some(key) => sequence.push(key),
+                None => return Err(format!(
+                    "unknown key <{}> in \"{}\"", special, keys)),
+            }
+            rest = &rest[end + 1..];
+            continue;
+        }
+        sequence.push(KeyPress::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    if sequence.is_empty() {
+        return Err("empty key binding".to_owned());
+    }
+    Ok(sequence)
+}
+
+pub fn format_keys(keys: &[KeyPress]) -> String {
+    keys.iter().map(|key| key.to_string()).collect()
+}
+
+// Everything a key can be bound to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Goto,
+    Back,
+    Forward,
+    Reload,
+    NewTab,
+    CloseTab,
+    NextTab,
+    PrevTab,
+    FollowHint,
+    FollowHintNewTab,
+    NextLink,
+    PrevLink,
+    FollowLink,
+    Search,
+    NextMatch,
+    PrevMatch,
+    Outline,
+    NextHeading,
+    PrevHeading,
+    Bookmark,
+    SaveAs,
+    ViewSource,
+    CopySource,
+    SaveSource,
+    ScrollDown,
+    ScrollUp,
+    ScrollLeft,
+    ScrollRight,
+    PageDown,
+    PageUp,
+    Top,
+    Bottom,
+    CommandLine,
+    Theme,
+    Quit,
+}
+
+impl Action {
+    pub const ALL: [Action; 35] = [
+        Action::Goto,
+        Action::Back,
+        Action::Forward,
+        Action::Reload,
+        Action::NewTab,
+        Action::CloseTab,
+        Action::NextTab,
+        Action::PrevTab,
+        Action::FollowHint,
+        Action::FollowHintNewTab,
+        Action::NextLink,
+        Action::PrevLink,
+        Action::FollowLink,
+        Action::Search,
+        Action::NextMatch,
+        Action::PrevMatch,
+        Action::Outline,
+        Action::NextHeading,
+        Action::PrevHeading,
+        Action::Bookmark,
+        Action::SaveAs,
+        Action::ViewSource,
+        Action::CopySource,
+        Action::SaveSource,
+        Action::ScrollDown,
+        Action::ScrollUp,
+        Action::ScrollLeft,
+        Action::ScrollRight,
+        Action::PageDown,
+        Action::PageUp,
+        Action::Top,
+        Action::Bottom,
+        Action::CommandLine,
+        Action::Theme,
+        Action::Quit,
+    ];
+
+    // The key used for this action in the [keys.bindings] config table.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Goto => "goto",
+            Action::Back => "back",
+            Action::Forward => "forward",
+            Action::Reload => "reload",
+            Action::NewTab => "new_tab",
+            Action::CloseTab => "close_tab",
+            Action::NextTab => "next_tab",
+            Action::PrevTab => "prev_tab",
+            Action::FollowHint => "follow_hint",
+            Action::FollowHintNewTab => "follow_hint_new_tab",
+            Action::NextLink => "next_link",
+            Action::PrevLink => "prev_link",
+            Action::FollowLink => "follow_link",
+            Action::Search => "search",
+            Action::NextMatch => "next_match",
+            Action::PrevMatch => "prev_match",
+            Action::Outline => "outline",
+            Action::NextHeading => "next_heading",
+            Action::PrevHeading => "prev_heading",
+            Action::Bookmark => "bookmark",
+            Action::SaveAs => "save_as",
+            Action::ViewSource => "view_source",
+            Action::CopySource => "copy_source",
+            Action::SaveSource => "save_source",
+            Action::ScrollDown => "scroll_down",
+            Action::ScrollUp => "scroll_up",
+            Action::ScrollLeft => "scroll_left",
+            Action::ScrollRight => "scroll_right",
+            Action::PageDown => "page_down",
+            Action::PageUp => "page_up",
+            Action::Top => "top",
+            Action::Bottom => "bottom",
+            Action::CommandLine => "command_line",
+            Action::Theme => "theme",
+            Action::Quit => "quit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().find(|a| a.name() == name).copied()
+    }
+
+    // A short description, for listing the bindings.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::Goto => "Go to a URL",
+            Action::Back => "Go back",
+            Action::Forward => "Go forward",
+            Action::Reload => "Reload the page",
+            Action::NewTab => "Open a new tab",
+            Action::CloseTab => "Close the tab",
+            Action::NextTab => "Switch to the next tab",
+            Action::PrevTab => "Switch to the previous tab",
+            Action::FollowHint => "Follow a link by its hint",
+            Action::FollowHintNewTab => "Open a link in a new tab by its hint",
+            Action::NextLink => "Select the next link",
+            Action::PrevLink => "Select the previous link",
+            Action::FollowLink => "Follow the selected link",
+            Action::Search => "Search the page",
+            Action::NextMatch => "Jump to the next match",
+            Action::PrevMatch => "Jump to the previous match",
+            Action::Outline => "Show the table of contents",
+            Action::NextHeading => "Jump to the next heading",
+            Action::PrevHeading => "Jump to the previous heading",
+            Action::Bookmark => "Bookmark the page",
+            Action::SaveAs => "Save the page as a file",
+            Action::ViewSource => "Switch between the page and its source",
+            Action::CopySource => "Copy the page source",
+            Action::SaveSource => "Save the page source as a file",
+            Action::ScrollDown => "Scroll down",
+            Action::ScrollUp => "Scroll up",
+            Action::ScrollLeft => "Scroll left",
+            Action::ScrollRight => "Scroll right",
+            Action::PageDown => "Scroll down a page",
+            Action::PageUp => "Scroll up a page",
+            Action::Top => "Go to the top of the page",
+            Action::Bottom => "Go to the bottom of the page",
+            Action::CommandLine => "Open the command line",
+            Action::Theme => "Pick a theme",
+            Action::Quit => "Quit",
+        }
+    }
+}
+
+// What a sequence of key presses amounts to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyMatch {
+    Action(Action),
+    // The start of a longer binding, so more keys are needed.
+    Prefix,
+    None,
+}
+
+// Key sequences and the actions they're bound to.
+#[derive(Clone, Debug, Default)]
+pub struct Keymap {
+    bindings: Vec<(Vec<KeyPress>, Action)>,
+}
+
+impl Keymap {
+    // Returns the keymap of one of PRESETS.
+    pub fn preset(name: &str) -> Option<Keymap> {
+        use Action::*;
+
+        let bindings: &[(Action, &[&str])] = match name {
+            "default" => &[
+                (Goto, &["g"]),
+                (Back, &["b", "<BS>"]),
+                (Forward, &["B"]),
+                (Reload, &["r"]),
+                (NewTab, &["<C-t>"]),
+                (CloseTab, &["<C-w>"]),
+                (NextTab, &["<Tab>"]),
+                (PrevTab, &["<S-Tab>"]),
+                (FollowHint, &["f"]),
+                (FollowHintNewTab, &["F"]),
+                (NextLink, &["<C-n>"]),
+                (PrevLink, &["<C-p>"]),
+                (FollowLink, &["<Enter>"]),
+                (Search, &["/"]),
+                (NextMatch, &["n"]),
+                (PrevMatch, &["N"]),
+                (Outline, &["o"]),
+                (NextHeading, &["]"]),
+                (PrevHeading, &["["]),
+                (Bookmark, &["<C-d>"]),
+                (SaveAs, &["s"]),
+                (ViewSource, &["<C-u>"]),
+                (PageDown, &["<Space>"]),
+                (CommandLine, &[":"]),
+                (Theme, &["t"]),
+                (Quit, &["q", "<Esc>"]),
+            ],
+            "vim" => &[
+                (Goto, &["o"]),
+                (Back, &["H"]),
+                (Forward, &["L"]),
+                (Reload, &["r"]),
+                (NewTab, &["O"]),
+                (CloseTab, &["d"]),
+                (NextTab, &["gt", "J"]),
+                (PrevTab, &["gT", "K"]),
+                (FollowHint, &["f"]),
+                (FollowHintNewTab, &["F"]),
+                (NextLink, &["<C-n>"]),
+                (PrevLink, &["<C-p>"]),
+                (FollowLink, &["<Enter>"]),
+                (Search, &["/"]),
+                (NextMatch, &["n"]),
+                (PrevMatch, &["N"]),
+                (Outline, &["gO"]),
+                (NextHeading, &["]]"]),
+                (PrevHeading, &["[["]),
+                (Bookmark, &["M"]),
+                (SaveAs, &["s"]),
+                (ViewSource, &["gf"]),
+                (ScrollDown, &["j"]),
+                (ScrollUp, &["k"]),
+                (ScrollLeft, &["h"]),
+                (ScrollRight, &["l"]),
+                (PageDown, &["<C-f>", "<Space>"]),
+                (PageUp, &["<C-b>"]),
+                (Top, &["gg"]),
+                (Bottom, &["G"]),
+                (CommandLine, &[":"]),
+                (Quit, &["ZZ", "<C-q>"]),
+            ],
+            // Mostly follows eww, the emacs web browser.
+            "emacs" => &[
+                (Goto, &["G", "<C-x><C-f>"]),
+                (Back, &["l"]),
+                (Forward, &["r"]),
+                (Reload, &["g"]),
+                (NewTab, &["<C-x>t2"]),
+                (CloseTab, &["<C-x>t0"]),
+                (NextTab, &["<C-x>to"]),
+                (PrevTab, &["<C-x>tO"]),
+                (FollowHint, &["o"]),
+                (FollowHintNewTab, &["O"]),
+                (NextLink, &["<Tab>"]),
+                (PrevLink, &["<S-Tab>"]),
+                (FollowLink, &["<Enter>"]),
+                (Search, &["<C-s>"]),
+                (NextMatch, &["<M-n>"]),
+                (PrevMatch, &["<M-p>"]),
+                (Outline, &["<M-g>i"]),
+                (NextHeading, &["<M-}>"]),
+                (PrevHeading, &["<M-{>"]),
+                (Bookmark, &["b"]),
+                (SaveAs, &["d"]),
+                (ViewSource, &["v"]),
+                (ScrollDown, &["<C-n>"]),
+                (ScrollUp, &["<C-p>"]),
+                (ScrollLeft, &["<C-b>"]),
+                (ScrollRight, &["<C-f>"]),
+                (PageDown, &["<C-v>", "<Space>"]),
+                (PageUp, &["<M-v>"]),
+                (Top, &["<M-lt>"]),
+                (Bottom, &["<M->>"]),
+                (CommandLine, &["<M-x>"]),
+                (Quit, &["<C-x><C-c>"]),
+            ],
+            _ => return None,
+        };
+
+        let mut keymap = Keymap::default();
+        for (action, keys) in bindings {
+            for keys in keys.iter() {
+                // The presets are written by hand, so they always parse.
+                keymap.bind(parse_keys(keys).unwrap(), *action);
+            }
+        }
+        Some(keymap)
+    }
+
+    pub fn bind(&mut self, keys: Vec<KeyPress>, action: Action) {
+        self.bindings.push((keys, action));
+    }
+
+    // Removes every binding of action.
+    pub fn unbind(&mut self, action: Action) {
+        self.bindings.retain(|(_, bound)| *bound != action);
+    }
+
+    pub fn bindings(&self) -> &[(Vec<KeyPress>, Action)] {
+        &self.bindings
+    }
+
+    pub fn keys_for(&self, action: Action) -> Vec<&[KeyPress]> {
+        self.bindings
+            .iter()
+            .filter(|(_, bound)| *bound == action)
+            .map(|(keys, _)| keys.as_slice())
+            .collect()
+    }
+
+    // Looks up the keys pressed so far. An exact match wins over longer
+    // bindings that start with the same keys.
+    pub fn lookup(&self, pressed: &[KeyPress]) -> KeyMatch {
+        if let Some((_, action)) = self.bindings.iter().find(|(keys, _)| keys == pressed) {
+            return KeyMatch::Action(*action);
+        }
+        if self.bindings.iter().any(|(keys, _)| keys.starts_with(pressed)) {
+            return KeyMatch::Prefix;
+        }
+        KeyMatch::None
+    }
+
+    // Adds key to the keys pressed so far and returns the action they now
+    // make up, if any. Keys that can't lead anywhere are dropped, though the
+    // last one may still start a binding of its own.
+    pub fn press(&self, pending: &mut Vec<KeyPress>, key: KeyPress) -> Option<Action> {
+        pending.push(key);
+        match self.lookup(pending) {
+            KeyMatch::Action(action) => {
+                pending.clear();
+                Some(action)
+            },
+            KeyMatch::Prefix => None,
+            KeyMatch::None => {
+                let retry = pending.len() > 1;
+                pending.clear();
+                if retry {
+                    self.press(pending, key)
+                } else {
+                    None
+                }
+            },
+        }
+    }
+
+    // Describes bindings that can't all work: the same keys bound to two
+    // actions, or keys that start a longer binding which can then never be
+    // reached.
+    pub fn conflicts(&self) -> Vec<String> {
+        let mut conflicts = Vec::new();
+        let mut seen: HashMap<&[KeyPress], Action> = HashMap::new();
+        for (keys, action) in &self.bindings {
+            match seen.get(keys.as_slice()) {
+                Some(other) if other != action => conflicts.push(format!(
+                    "{} is bound to both {} and {}",
+                    format_keys(keys), other.name(), action.name())),
+                Some(_) => {},
+                None => {
+                    seen.insert(keys, *action);
+                },
+            }
+        }
+        for (keys, action) in &self.bindings {
+            for (longer, other) in &self.bindings {
+                if longer.len() > keys.len() && longer.starts_with(keys) {
+                    conflicts.push(format!(
+                        "{} ({}) hides {} ({})",
+                        format_keys(keys), action.name(),
+                        format_keys(longer), other.name()));
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_parse() {
+        assert_eq!(parse_keys("gg"), Ok(vec![KeyPress::Char('g'), KeyPress::Char('g')]));
+        assert_eq!(parse_keys("<C-x><C-F>"), Ok(vec![KeyPress::Ctrl('x'), KeyPress::Ctrl('f')]));
+        assert_eq!(parse_keys("<S-Tab>"), Ok(vec![KeyPress::Shift(NamedKey::Tab)]));
+        assert_eq!(parse_keys("<space><M-lt>"), Ok(vec![KeyPress::Char(' '), KeyPress::Alt('<')]));
+        assert_eq!(parse_keys("<"), Ok(vec![KeyPress::Char('<')]));
+        assert_eq!(parse_keys("<M->>"), Ok(vec![KeyPress::Alt('>')]));
+        assert!(parse_keys("<Nope>").is_err());
+        assert!(parse_keys("").is_err());
+        assert_eq!(format_keys(&parse_keys("<C-x>t2").unwrap()), "<C-x>t2");
+    }
+
+    #[test]
+    fn chords_wait_for_more_keys() {
+        let keymap = Keymap::preset("vim").unwrap();
+        let g = parse_keys("g").unwrap();
+        assert_eq!(keymap.lookup(&g), KeyMatch::Prefix);
+        assert_eq!(keymap.lookup(&parse_keys("gg").unwrap()), KeyMatch::Action(Action::Top));
+        assert_eq!(keymap.lookup(&parse_keys("x").unwrap()), KeyMatch::None);
+
+        let mut pending = Vec::new();
+        assert_eq!(keymap.press(&mut pending, KeyPress::Char('g')), None);
+        assert_eq!(keymap.press(&mut pending, KeyPress::Char('j')), Some(Action::ScrollDown));
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn presets_have_no_conflicts() {
+        for name in PRESETS.iter() {
+            let keymap = Keymap::preset(name).unwrap();
+            assert_eq!(keymap.conflicts(), Vec::<String>::new(), "in {}", name);
+        }
+    }
+
+    #[test]
+    fn conflicts_are_reported() {
+        let mut keymap = Keymap::preset("vim").unwrap();
+        keymap.bind(parse_keys("g").unwrap(), Action::Goto);
+        keymap.bind(parse_keys("j").unwrap(), Action::Reload);
+        let conflicts = keymap.conflicts();
+        assert!(conflicts.contains(&"j is bound to both scroll_down and reload".to_owned()));
+        assert!(conflicts.contains(&"g (goto) hides gg (top)".to_owned()));
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+
+use unicode_bidi::{bidi_class, BidiClass, BidiInfo, Level};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+use crate::gemtext::{GemtextToken, TokenKind};
+use crate::highlight::{highlight_lines, HighlightSpan};
+
+const TAB_WIDTH: usize = 8;
+
+// A single row of a laid out page.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayoutLine {
+    // Index of the token in the chain this row belongs to.
+    pub token: usize,
+    pub kind: TokenKind,
+    // The row text, including the token's marker or hanging indent.
+    pub text: String,
+    // Syntax highlighted ranges of text, only set in preformatted blocks.
+    pub spans: Vec<HighlightSpan>,
+    // Where the row's words came from in the line they were wrapped from.
+    // Preformatted rows and rows reordered for bidi don't have one, since
+    // their text can't be read back in order.
+    pub source: Option<RowSource>,
+}
+
+// How a row wrapped from a line of text fits back into it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RowSource {
+    // The byte in the row's text the line's words start at, after any
+    // marker or indent.
+    pub start: usize,
+    // Whether the space between two words was dropped where the row above
+    // ended, rather than a word too long for a row being broken.
+    pub space_before: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Direction {
+    LeftToRight,
+    RightToLeft,
+}
+
+// Everything besides the token chain that decides how a page is laid out.
+#[derive(Clone, Debug, Default)]
+pub struct LayoutOptions {
+    // Cells to wrap at, 0 turns wrapping off.
+    pub width: usize,
+    // Whether to apply the Unicode bidi algorithm to each line.
+    pub bidi: bool,
+    // Base direction suggested by the lang parameter of the response.
+    pub direction_hint: Option<Direction>,
+    // Whether to highlight preformatted blocks whose alt text names a
+    // language.
+    pub highlight: bool,
+    // Markers to draw instead of a token's own, keyed by token index.
+    pub markers: HashMap<usize, &'static str>,
+}
+
+// Maps a lang parameter, such as "he" or "ar-EG,en", onto the direction its
+// script is written in.
+pub fn direction_for_lang(lang: &str) -> Direction {
+    let primary = lang
+        .split(',')
+        .next()
+        .unwrap_or("")
+        .split(&['-', '_'][..])
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase();
+    match primary.as_str() {
+        "ar" | "arc" | "ckb" | "dv" | "fa" | "he" | "iw" | "ks" | "ps"
+            | "sd" | "syr" | "ug" | "ur" | "yi" => Direction::RightToLeft,
+        _ => Direction::LeftToRight,
+    }
+}
+
+fn is_rtl_char(c: char) -> bool {
+    matches!(bidi_class(c), BidiClass::R | BidiClass::AL)
+}
+
+// Works out the base direction of a line. Normally the first strong character
+// decides, but a right-to-left hint wins for any line with right-to-left text
+// in it so mixed lines on, say, a Hebrew page still read right to left.
+fn base_direction(text: &str, hint: Option<Direction>) -> Direction {
+    if hint == Some(Direction::RightToLeft) && text.chars().any(is_rtl_char) {
+        return Direction::RightToLeft;
+    }
+    let info = BidiInfo::new(text, None);
+    match info.paragraphs.first() {
+        Some(para) if para.level.is_rtl() => Direction::RightToLeft,
+        _ => Direction::LeftToRight,
+    }
+}
+
+// Reorders a single line from logical into visual order.
+fn reorder_line(text: &str, direction: Direction) -> String {
+    let level = match direction {
+        Direction::LeftToRight => Level::ltr(),
+        Direction::RightToLeft => Level::rtl(),
+    };
+    let info = BidiInfo::new(text, Some(level));
+    match info.paragraphs.first() {
+        Some(para) => info.reorder_line(para, para.range.clone()).into_owned(),
+        None => text.to_owned(),
+    }
+}
+
+// Flips a marker so it can be drawn on the right of a right-to-left line.
+fn mirror_marker(marker: &str) -> String {
+    marker
+        .chars()
+        .rev()
+        .map(|c| if c == '→' { '←' } else { c })
+        .collect()
+}
+
+// Returns how many terminal cells a single grapheme cluster takes up.
+// Combining marks and joiners don't add to the width of the cluster they're
+// in, and a variation selector asking for emoji presentation makes it wide.
+// So does a pair of regional indicators, which is drawn as a flag.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    let is_regional_indicator = |c: char| ('\u{1F1E6}'..='\u{1F1FF}').contains(&c);
+    if grapheme.contains('\u{FE0F}')
+        || grapheme.chars().filter(|&c| is_regional_indicator(c)).count() == 2 {
+        return 2;
+    }
+    grapheme
+        .chars()
+        .next()
+        .and_then(|c| c.width())
+        .unwrap_or(0)
+}
+
+// Returns how many terminal cells the given text takes up.
+pub fn text_width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}
+
+// Splits a word that is too long to fit on a line into a piece that fits in
+// width cells and the rest. Grapheme clusters are never split and at least
+// one cluster always goes in the first piece.
+fn split_at_width(word: &str, width: usize) -> (String, String) {
+    let mut used = 0;
+    let mut split = word.len();
+    for (i, grapheme) in word.grapheme_indices(true) {
+        let w = grapheme_width(grapheme);
+        if used + w > width && i > 0 {
+            split = i;
+            break;
+        }
+        used += w;
+    }
+    (word[..split].to_owned(), word[split..].to_owned())
+}
+
+// Replaces tabs with spaces up to the next tab stop so preformatted text
+// lines up the same way it would in a terminal.
+pub fn expand_tabs(line: &str) -> String {
+    if !line.contains('\t') {
+        return line.to_owned();
+    }
+    let mut expanded = String::new();
+    let mut column = 0;
+    for grapheme in line.graphemes(true) {
+        if grapheme == "\t" {
+            let spaces = TAB_WIDTH - column % TAB_WIDTH;
+            expanded.push_str(&" ".repeat(spaces));
+            column += spaces;
+        } else {
+            expanded.push_str(grapheme);
+            column += grapheme_width(grapheme);
+        }
+    }
+    expanded
+}
+
+// Greedily wraps text into lines at most width columns wide, breaking words
+// only when a single word is wider than the whole line. A width of 0 turns
+// wrapping off.
+pub fn wrap_text(text: &str, width: usize) -> Vec<String> {
+    if width == 0 || text_width(text) <= width {
+        return vec![text.to_owned()];
+    }
+
+    let mut lines = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word.to_owned();
+        loop {
+            let needed = if current.is_empty() {
+                text_width(&word)
+            } else {
+                text_width(&current) + 1 + text_width(&word)
+            };
+            if needed <= width {
+                if !current.is_empty() {
+                    current.push(' ');
+                }
+                current.push_str(&word);
+                break;
+            }
+            if !current.is_empty() {
+                lines.push(std::mem::take(&mut current));
+                continue;
+            }
+            let (head, tail) = split_at_width(&word, width);
+            lines.push(head);
+            word = tail;
+        }
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(current);
+    }
+    lines
+}
+
+// Lays out a token chain into rows for a page options.width cells wide.
+// Links, list items and quotes get hanging indents under their markers and
+// preformatted blocks are never wrapped. With bidi on, each line is wrapped
+// in logical order first and then every row is reordered for display, with
+// right-to-left rows aligned to the right edge.
+pub fn layout_gemtext(chain: &[GemtextToken], options: &LayoutOptions)
+    -> Vec<LayoutLine> {
+    let width = options.width;
+    let mut lines = Vec::new();
+
+    for (i, token) in chain.iter().enumerate() {
+        if token.kind == TokenKind::PreFormattedText {
+            let block: Vec<String> = token.data
+                .lines()
+                .map(|line| expand_tabs(line.trim_end_matches('\r')))
+                .collect();
+            let highlighted = if options.highlight {
+                highlight_lines(&token.extra, &block)
+            } else {
+                None
+            };
+            let mut highlighted = highlighted.unwrap_or_default().into_iter();
+            for text in block {
+                lines.push(LayoutLine {
+                    token: i,
+                    kind: token.kind,
+                    text,
+                    spans: highlighted.next().unwrap_or_default(),
+                    source: None,
+                });
+            }
+            continue;
+        }
+
+        let marker = options.markers.get(&i).copied().unwrap_or_else(|| token.marker());
+        let indent = " ".repeat(text_width(marker));
+        let available = if width == 0 {
+            0
+        } else {
+            width.saturating_sub(text_width(marker)).max(1)
+        };
+        // Top level headings are set in capitals to stand out from the
+        // sub headings below them.
+        let display_text = match token.kind {
+            TokenKind::Heading => token.display_text().to_uppercase(),
+            _ => token.display_text(),
+        };
+        let direction = if options.bidi {
+            base_direction(&display_text, options.direction_hint)
+        } else {
+            Direction::LeftToRight
+        };
+        let wrapped = wrap_text(&display_text, available);
+        // Rows wrapped from a longer line have their words separated by
+        // single spaces, which are dropped where a row ends between words.
+        let words = display_text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut taken = 0;
+        for (n, text) in wrapped.into_iter().enumerate() {
+            let prefix = if n == 0 { marker } else { &indent };
+            let space_before = n > 0 && words.get(taken..).is_some_and(|rest| rest.starts_with(' '));
+            taken += text.len() + usize::from(space_before);
+            let (text, reordered) = if options.bidi {
+                let reordered = reorder_line(&text, direction);
+                let changed = reordered != text;
+                (reordered, changed)
+            } else {
+                (text, false)
+            };
+            let (row, source) = match direction {
+                Direction::LeftToRight => {
+                    let source = RowSource {
+                        start: prefix.len(),
+                        space_before,
+                    };
+                    (format!("{}{}", prefix, text), Some(source).filter(|_| !reordered))
+                },
+                Direction::RightToLeft => {
+                    let row = format!("{}{}", text, mirror_marker(prefix));
+                    let padding = width.saturating_sub(text_width(&row));
+                    (format!("{}{}", " ".repeat(padding), row), None)
+                },
+            };
+            lines.push(LayoutLine {
+                token: i,
+                kind: token.kind,
+                text: row,
+                spans: Vec::new(),
+                source,
+            });
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn wrap_breaks_on_spaces() {
+        let wrapped = wrap_text("the quick brown fox jumps", 10);
+        assert_eq!(wrapped, vec!["the quick", "brown fox", "jumps"]);
+    }
+
+    #[test]
+    fn wrap_splits_overlong_words() {
+        let wrapped = wrap_text("abcdefghij", 4);
+        assert_eq!(wrapped, vec!["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn wide_and_combining_characters_are_measured_in_cells() {
+        assert_eq!(text_width("日本語"), 6);
+        assert_eq!(text_width("e\u{301}te\u{301}"), 3);
+        assert_eq!(text_width("👩\u{200D}💻"), 2);
+        assert_eq!(text_width("❤\u{FE0F}"), 2);
+    }
+
+    #[test]
+    fn flags_are_two_cells_wide() {
+        // The flags of Israel and Japan.
+        assert_eq!(text_width("\u{1F1EE}\u{1F1F1}"), 2);
+        assert_eq!(text_width("\u{1F1EE}\u{1F1F1}\u{1F1EF}\u{1F1F5}"), 4);
+        assert_eq!(wrap_text("\u{1F1EE}\u{1F1F1} \u{1F1EF}\u{1F1F5}", 4).len(), 2);
+    }
+
+    #[test]
+    fn wrap_never_splits_wide_characters() {
+        let wrapped = wrap_text("日本語のテキスト", 5);
+        assert_eq!(wrapped, vec!["日本", "語の", "テキ", "スト"]);
+    }
+
+    #[test]
+    fn tabs_expand_to_tab_stops() {
+        assert_eq!(expand_tabs("ab\tc"), "ab      c");
+        assert_eq!(expand_tabs("日\tc"), "日      c");
+    }
+
+    #[test]
+    fn list_items_get_hanging_indents() {
+        let chain = parse_gemtext("* one two three four\n> five six seven\n");
+        let options = LayoutOptions { width: 10, ..Default::default() };
+        let lines = layout_gemtext(&chain, &options);
+        let text: Vec<&str> = lines.iter().map(|l| l.text.as_str()).collect();
+        assert_eq!(text, vec![
+            "• one two",
+            "  three",
+            "  four",
+            "> five six",
+            "  seven",
+        ]);
+    }
+
+    #[test]
+    fn pft_is_never_wrapped() {
+        let raw_text =
+            "```\n\
+            a very long preformatted line\n\
+            ```";
+        let options = LayoutOptions { width: 5, ..Default::default() };
+        let lines = layout_gemtext(&parse_gemtext(raw_text), &options);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text, "a very long preformatted line");
+    }
+
+    #[test]
+    fn rtl_lines_are_reordered_and_right_aligned() {
+        let chain = parse_gemtext("* שלום עולם\n");
+        let options = LayoutOptions { width: 12, bidi: true, ..Default::default() };
+        let lines = layout_gemtext(&chain, &options);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text, " םלוע םולש •");
+    }
+
+    #[test]
+    fn lang_hint_sets_direction() {
+        assert_eq!(direction_for_lang("he"), Direction::RightToLeft);
+        assert_eq!(direction_for_lang("ar-EG,en"), Direction::RightToLeft);
+        assert_eq!(direction_for_lang("en-US"), Direction::LeftToRight);
+
+        let hint = Some(Direction::RightToLeft);
+        assert_eq!(base_direction("Rust היא שפה", hint), Direction::RightToLeft);
+        assert_eq!(base_direction("Rust היא שפה", None), Direction::LeftToRight);
+    }
+}
@@ -0,0 +1,43 @@
+pub mod about;
+pub mod address;
+pub mod bookmarks;
+pub mod cli;
+pub mod clipboard;
+pub mod commands;
+pub mod downloads;
+pub mod fetch;
+pub mod gemtext;
+pub mod handlers;
+pub mod highlight;
+pub mod history;
+pub mod image_art;
+pub mod keymap;
+pub mod layout;
+pub mod links;
+pub mod markdown;
+pub mod render;
+pub mod search;
+pub mod session;
+
+pub mod transaction {
+    pub mod identity;
+    pub mod response;
+    pub mod tofu;
+    pub mod visit;
+}
+
+pub mod ui {
+    pub mod tui;
+    pub mod address_bar;
+    pub mod browser;
+    pub mod command_line;
+    pub mod completions;
+    pub mod downloads;
+    pub mod handlers;
+    pub mod page_view;
+    pub mod styles;
+    pub mod tab_bar;
+}
+
+pub mod settings;
+pub mod theme;
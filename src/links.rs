@@ -0,0 +1,158 @@
+use url::Url;
+
+use crate::gemtext::{GemtextToken, TokenKind};
+
+const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "webp", "bmp", "svg"];
+
+// Where a link leads, as far as can be told without following it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LinkKind {
+    // A gemini page on the same capsule.
+    Gemini,
+    // A gemini page on another host.
+    CrossCapsule,
+    Image,
+    Web,
+    Gopher,
+    Mail,
+    // Any other scheme, or a target that couldn't be parsed.
+    Other,
+}
+
+impl LinkKind {
+    // The marker drawn in front of links of this kind.
+    pub fn marker(&self) -> &'static str {
+        match self {
+            LinkKind::Gemini => "→ ",
+            LinkKind::CrossCapsule => "⇒ ",
+            LinkKind::Image => "▣ ",
+            LinkKind::Web => "⇗ ",
+            LinkKind::Gopher => "⇝ ",
+            LinkKind::Mail => "✉ ",
+            LinkKind::Other => "↗ ",
+        }
+    }
+
+    // Whether following the link leaves gemini space.
+    pub fn is_external(&self) -> bool {
+        matches!(self, LinkKind::Web | LinkKind::Gopher | LinkKind::Mail | LinkKind::Other)
+    }
+}
+
+// A link line on a page with its target resolved against the page's URL.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageLink {
+    // Index of the link's token in the chain.
+    pub token: usize,
+    pub url: Option<Url>,
+    pub kind: LinkKind,
+    pub visited: bool,
+}
+
+// Resolves a link target, which may be relative, against the page it's on.
+pub fn resolve_link(base: Option<&Url>, target: &str) -> Option<Url> {
+    let target = target.trim();
+    match base {
+        Some(base) => base.join(target).ok(),
+        None => Url::parse(target).ok(),
+    }
+}
+
+pub fn classify_link(base: Option<&Url>, url: &Url) -> LinkKind {
+    if url.scheme() == "mailto" {
+        return LinkKind::Mail;
+    }
+    let extension = url.path()
+        .rsplit('/')
+        .next()
+        .and_then(|name| name.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_lowercase());
+    if let Some(extension) = extension {
+        if IMAGE_EXTENSIONS.contains(&extension.as_str()) {
+            return LinkKind::Image;
+        }
+    }
+    match url.scheme() {
+        "gemini" => {
+            let same_host = base.map(|b| b.host_str() == url.host_str()).unwrap_or(true);
+            if same_host {
+                LinkKind::Gemini
+            } else {
+                LinkKind::CrossCapsule
+            }
+        },
+        "http" | "https" => LinkKind::Web,
+        "gopher" => LinkKind::Gopher,
+        _ => LinkKind::Other,
+    }
+}
+
+// Collects every link on a page. is_visited decides which targets have been
+// seen before, normally by asking the history store.
+pub fn page_links<F>(chain: &[GemtextToken], base: Option<&Url>, is_visited: F)
+    -> Vec<PageLink>
+where
+    F: Fn(&Url) -> bool,
+{
+    chain
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.kind == TokenKind::Link)
+        .map(|(i, token)| {
+            let url = resolve_link(base, &token.data);
+            let kind = match &url {
+                Some(url) => classify_link(base, url),
+                None => LinkKind::Other,
+            };
+            let visited = url.as_ref().map(&is_visited).unwrap_or(false);
+            PageLink {
+                token: i,
+                url,
+                kind,
+                visited,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn links_are_classified_by_target() {
+        let base = Url::parse("gemini://example.org/dir/index.gmi").unwrap();
+        let raw_text =
+            "\
+            => page.gmi\n\
+            => gemini://other.org/ Elsewhere\n\
+            => /cat.PNG A cat\n\
+            => https://example.com/ The web\n\
+            => gopher://example.net/ A hole\n\
+            => mailto:me@example.org Mail me\n\
+            => finger://example.org/ Finger\n";
+        let links = page_links(&parse_gemtext(raw_text), Some(&base), |_| false);
+        let kinds: Vec<LinkKind> = links.iter().map(|l| l.kind).collect();
+        assert_eq!(kinds, vec![
+            LinkKind::Gemini,
+            LinkKind::CrossCapsule,
+            LinkKind::Image,
+            LinkKind::Web,
+            LinkKind::Gopher,
+            LinkKind::Mail,
+            LinkKind::Other,
+        ]);
+        assert_eq!(links[0].url.as_ref().unwrap().as_str(),
+            "gemini://example.org/dir/page.gmi");
+    }
+
+    #[test]
+    fn visited_links_are_marked() {
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let chain = parse_gemtext("=> seen.gmi\n=> unseen.gmi\n");
+        let links = page_links(&chain, Some(&base), |url| url.path() == "/seen.gmi");
+        assert!(links[0].visited);
+        assert!(!links[1].visited);
+    }
+}
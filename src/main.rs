@@ -1,23 +1,98 @@
-pub mod gemtext;
-
-pub mod transaction {
-    pub mod dummy_verifier;
-    pub mod response;
-    pub mod visit;
-}
-
-pub mod ui {
-    pub mod tui;
-    pub mod browser;
-}
-
-pub mod settings;
+use std::env;
+use std::process;
 
 use cursive::CursiveExt;
-use ui::tui::*;
+use armstrong::address::parse_address;
+use armstrong::bookmarks::Bookmarks;
+use armstrong::cli::{parse_args, Command, USAGE};
+use armstrong::fetch::run_fetch;
+use armstrong::handlers::remove_temp_files;
+use armstrong::history::History;
+use armstrong::render::run_render;
+use armstrong::session::Session;
+use armstrong::settings::{default_data_dir, load_keymap, load_settings, load_theme};
+use armstrong::transaction::tofu::KnownHosts;
+use armstrong::ui::tui::*;
 
 fn main() {
-    // println!("Hello I'm not setup yet, try running `cargo test`");
-    let mut app = init_ui();
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Command::Browse(options)) => options,
+        Ok(Command::Fetch(options)) => process::exit(run_fetch(&options)),
+        Ok(Command::Render(options)) => process::exit(run_render(&options)),
+        Ok(Command::Version) => {
+            println!("armstrong {}", env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+            return;
+        },
+        Err(e) => {
+            eprintln!("armstrong: {}\nTry armstrong --help for more information.", e);
+            process::exit(2);
+        }
+    };
+    let mut settings = match load_settings(&options.config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    if let Some(theme) = options.theme {
+        settings.theme.name = theme;
+    }
+    let theme = match load_theme(&settings.theme) {
+        Ok(theme) => theme,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let keymap = match load_keymap(&settings.keys) {
+        Ok(keymap) => keymap,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let urls = match options.urls
+        .iter()
+        .map(|url| parse_address(url, &settings.general.search_url))
+        .collect::<Result<Vec<_>, _>>() {
+        Ok(urls) => urls,
+        Err(e) => {
+            eprintln!("armstrong: {}", e);
+            process::exit(2);
+        }
+    };
+
+    // Private browsing leaves no trace of where it went: history and the
+    // certificates seen are only kept in memory and the session is neither
+    // restored nor saved.
+    let (history, known_hosts) = if options.private {
+        (History::in_memory(), KnownHosts::in_memory())
+    } else {
+        (History::load(&default_data_dir().join("history")),
+         KnownHosts::load(&default_data_dir().join("known_hosts")))
+    };
+    let bookmarks = Bookmarks::load(&default_data_dir().join("bookmarks.gmi"));
+    let session_path = default_data_dir().join("session.toml");
+    let session = if settings.general.restore_session && !options.private {
+        Some(Session::load(&session_path))
+    } else {
+        None
+    };
+    let mut app = init_ui(settings, history, bookmarks, known_hosts, theme, keymap);
+    open_start_page(&mut app, session, urls);
+    if !options.private {
+        save_session_periodically(&mut app, session_path.clone());
+    }
     app.run();
+    if !options.private {
+        if let Err(e) = save_session(&mut app, &session_path) {
+            eprintln!("Couldn't save the session: {}", e);
+        }
+    }
+    remove_temp_files();
 }
@@ -0,0 +1,430 @@
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Parser, Tag};
+
+use crate::gemtext::{GemtextToken, TokenKind};
+
+// Keeps track of where we are in the Markdown document while it is being
+// flattened into gemtext lines.
+struct MarkdownConverter {
+    lines: Vec<String>,
+    current: String,
+    // Links found in the current block, written out as => lines once the
+    // block is finished.
+    pending_links: Vec<(String, String)>,
+    // (destination, label so far) for each link being read. Labels are
+    // gathered on their own since a hard break flushes `current` midway.
+    open_links: Vec<(String, String)>,
+    // One entry per nested list, holding the next number for ordered lists.
+    lists: Vec<Option<u64>>,
+    item_prefix: Option<String>,
+    heading_level: Option<HeadingLevel>,
+    quote_depth: usize,
+}
+
+impl MarkdownConverter {
+    fn new() -> MarkdownConverter {
+        MarkdownConverter {
+            lines: Vec::new(),
+            current: String::new(),
+            pending_links: Vec::new(),
+            open_links: Vec::new(),
+            lists: Vec::new(),
+            item_prefix: None,
+            heading_level: None,
+            quote_depth: 0,
+        }
+    }
+
+    // Writes out the text gathered so far as a single gemtext line, prefixed
+    // according to the block it belongs to, followed by any links it held.
+    fn flush_line(&mut self) {
+        let text = self.current.trim().to_owned();
+        self.current.clear();
+        // A line that's nothing but a link is just its => line.
+        let only_link = match self.pending_links.as_slice() {
+            [(url, label)] => text == *label || text == *url,
+            _ => false,
+        };
+        if !text.is_empty() && !only_link {
+            let prefix = if let Some(level) = self.heading_level {
+                match level {
+                    HeadingLevel::H1 => "# ".to_owned(),
+                    HeadingLevel::H2 => "## ".to_owned(),
+                    _ => "### ".to_owned(),
+                }
+            } else if let Some(item_prefix) = self.item_prefix.take() {
+                item_prefix
+            } else if !self.lists.is_empty() {
+                // Continuation text inside a list item.
+                "* ".to_owned()
+            } else if self.quote_depth > 0 {
+                "> ".to_owned()
+            } else {
+                String::new()
+            };
+            self.lines.push(format!("{}{}", prefix, text));
+        }
+
+        for (url, label) in self.pending_links.drain(..) {
+            if label.is_empty() || label == url {
+                self.lines.push(format!("=> {}", url));
+            } else {
+                self.lines.push(format!("=> {} {}", url, label));
+            }
+        }
+    }
+
+    // Separates top level blocks with a single blank line.
+    fn end_block(&mut self) {
+        if self.lists.is_empty() && self.quote_depth == 0 {
+            self.blank_line();
+        }
+    }
+
+    fn blank_line(&mut self) {
+        if let Some(last) = self.lines.last() {
+            if !last.is_empty() {
+                self.lines.push(String::new());
+            }
+        }
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            Tag::Heading(level, _, _) => {
+                self.flush_line();
+                self.heading_level = Some(level);
+            },
+            Tag::BlockQuote => {
+                self.flush_line();
+                self.quote_depth += 1;
+            },
+            Tag::CodeBlock(kind) => {
+                self.flush_line();
+                let alt = match kind {
+                    CodeBlockKind::Fenced(lang) => lang.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.lines.push(format!("```{}", alt));
+            },
+            Tag::List(start) => {
+                // Nested lists are flattened, so whatever the parent item
+                // said so far gets its own line.
+                self.flush_line();
+                self.lists.push(start);
+            },
+            Tag::Item => {
+                self.flush_line();
+                let prefix = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        let prefix = format!("* {}. ", number);
+                        *number += 1;
+                        prefix
+                    },
+                    _ => "* ".to_owned(),
+                };
+                self.item_prefix = Some(prefix);
+            },
+            Tag::Link(_, dest, _) | Tag::Image(_, dest, _) => {
+                self.open_links.push((dest.to_string(), String::new()));
+            },
+            _ => (),
+        }
+    }
+
+    fn end(&mut self, tag: Tag) {
+        match tag {
+            Tag::Paragraph => {
+                self.flush_line();
+                self.end_block();
+            },
+            Tag::Heading(_, _, _) => {
+                self.flush_line();
+                self.heading_level = None;
+                self.end_block();
+            },
+            Tag::BlockQuote => {
+                self.flush_line();
+                self.quote_depth -= 1;
+                self.end_block();
+            },
+            Tag::CodeBlock(_) => {
+                // Code block text always ends in a newline.
+                let code = self.current.clone();
+                self.current.clear();
+                for line in code.lines() {
+                    self.lines.push(line.to_owned());
+                }
+                self.lines.push("```".to_owned());
+                self.end_block();
+            },
+            Tag::List(_) => {
+                self.flush_line();
+                self.lists.pop();
+                self.end_block();
+            },
+            Tag::Item => {
+                self.flush_line();
+                self.item_prefix = None;
+            },
+            Tag::Link(_, _, _) | Tag::Image(_, _, _) => {
+                if let Some((dest, label)) = self.open_links.pop() {
+                    // A nested image's alt text is part of the link's label.
+                    if let Some((_, outer)) = self.open_links.last_mut() {
+                        outer.push_str(&label);
+                    }
+                    self.pending_links.push((dest, label.trim().to_owned()));
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // Adds inline text to the current line and to the label of any link
+    // it's inside.
+    fn push_text(&mut self, text: &str) {
+        self.current.push_str(text);
+        if let Some((_, label)) = self.open_links.last_mut() {
+            label.push_str(text);
+        }
+    }
+
+    fn convert(mut self, markdown: &str) -> String {
+        for event in Parser::new(markdown) {
+            match event {
+                Event::Start(tag) => self.start(tag),
+                Event::End(tag) => self.end(tag),
+                Event::Text(text) => self.push_text(&text),
+                Event::Code(code) => self.push_text(&format!("`{}`", code)),
+                Event::SoftBreak => self.push_text(" "),
+                Event::HardBreak => {
+                    self.flush_line();
+                    if let Some((_, label)) = self.open_links.last_mut() {
+                        label.push(' ');
+                    }
+                },
+                Event::Rule => {
+                    self.flush_line();
+                    self.lines.push("---".to_owned());
+                    self.end_block();
+                },
+                Event::TaskListMarker(done) => {
+                    self.current.push_str(if done { "[x] " } else { "[ ] " });
+                },
+                _ => (),
+            }
+        }
+        self.flush_line();
+
+        while let Some(last) = self.lines.last() {
+            if last.is_empty() {
+                self.lines.pop();
+            } else {
+                break;
+            }
+        }
+        let mut gemtext = self.lines.join("\n");
+        gemtext.push('\n');
+        gemtext
+    }
+}
+
+// Converts a Markdown document into gemtext. Inline links are pulled out onto
+// their own => lines after the block they appear in, nested lists are
+// flattened and code fences become preformatted blocks.
+pub fn markdown_to_gemtext(markdown: &str) -> String {
+    MarkdownConverter::new().convert(markdown)
+}
+
+// Escapes the characters Markdown reads as inline markup: emphasis, code,
+// links, HTML, entities and the backslash itself.
+fn escape_inline(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<' | '>' | '&') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Escapes a line of gemtext text so Markdown shows it as it is. Leading
+// spaces are dropped, since four of them would start a code block, and
+// anything at the start that Markdown would read as a block marker is
+// escaped along with the inline markup.
+fn escape_markdown_line(line: &str) -> String {
+    let line = escape_inline(line.trim_start());
+    let number = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if number > 0 && matches!(line[number..].chars().next(), Some('.') | Some(')')) {
+        return format!("{}\\{}", &line[..number], &line[number..]);
+    }
+    match line.chars().next() {
+        Some('#') | Some('-') | Some('+') | Some('=') | Some('~') => {
+            format!("\\{}", line)
+        },
+        _ => line,
+    }
+}
+
+// Escapes heading text, including any # in it, since a heading ending in
+// #s would lose them as a closing sequence.
+fn escape_heading(text: &str) -> String {
+    escape_inline(text.trim_start()).replace('#', "\\#")
+}
+
+// A link destination, in angle brackets so it can hold spaces and
+// parentheses, which gemtext allows.
+fn markdown_destination(url: &str) -> String {
+    format!("<{}>", url.replace('\\', "\\\\").replace('<', "\\<").replace('>', "\\>"))
+}
+
+// Converts a gemtext token chain, as returned by parse_gemtext, into
+// Markdown. Each gemtext line becomes its own Markdown block except for
+// consecutive list items which stay in a single list.
+pub fn gemtext_to_markdown(chain: &[GemtextToken]) -> String {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut last_kind: Option<TokenKind> = None;
+
+    for token in chain {
+        let data = token.data.trim_end_matches(&['\r', '\n'][..]);
+        let extra = token.extra.trim_end_matches(&['\r', '\n'][..]);
+        let block = match token.kind {
+            TokenKind::Text => {
+                if data.trim().is_empty() {
+                    // Blank lines only separate blocks, which already happens.
+                    continue;
+                }
+                escape_markdown_line(data)
+            },
+            TokenKind::Link => {
+                let label = if extra.is_empty() { data } else { extra };
+                format!("[{}]({})", escape_inline(label), markdown_destination(data))
+            },
+            TokenKind::UnorderedList => format!("* {}", escape_markdown_line(data)),
+            TokenKind::Blockquote => format!("> {}", escape_markdown_line(data)),
+            TokenKind::Heading => format!("# {}", escape_heading(data)),
+            TokenKind::SubHeading => format!("## {}", escape_heading(data)),
+            TokenKind::SubSubHeading => format!("### {}", escape_heading(data)),
+            TokenKind::PreFormattedText => {
+                format!("```{}\n{}```", extra, token.data)
+            },
+        };
+
+        let tight = token.kind == TokenKind::UnorderedList
+            && last_kind == Some(TokenKind::UnorderedList);
+        match blocks.last_mut() {
+            Some(last) if tight => {
+                last.push('\n');
+                last.push_str(&block);
+            },
+            _ => blocks.push(block),
+        }
+        last_kind = Some(token.kind);
+    }
+
+    let mut markdown = blocks.join("\n\n");
+    markdown.push('\n');
+    markdown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn markdown_inline_links_become_link_lines() {
+        let markdown = "See [the spec](gemini://example.org/spec) for more.";
+        let gemtext = markdown_to_gemtext(markdown);
+        assert_eq!(gemtext,
+            "See the spec for more.\n\
+            => gemini://example.org/spec the spec\n");
+    }
+
+    #[test]
+    fn markdown_link_labels_can_span_hard_breaks() {
+        let gemtext = markdown_to_gemtext("abc [foo\\\nbar](x)");
+        assert_eq!(gemtext, "abc foo\nbar\n=> x foo bar\n");
+    }
+
+    #[test]
+    fn markdown_nested_lists_are_flattened() {
+        let markdown = "* one\n  * two\n    * three\n* four";
+        let gemtext = markdown_to_gemtext(markdown);
+        assert_eq!(gemtext, "* one\n* two\n* three\n* four\n");
+    }
+
+    #[test]
+    fn markdown_headings_and_quotes_convert() {
+        let markdown = "# Title\n\n#### Deep\n\n> quoted";
+        let gemtext = markdown_to_gemtext(markdown);
+        assert_eq!(gemtext, "# Title\n\n### Deep\n\n> quoted\n");
+    }
+
+    #[test]
+    fn markdown_code_fences_become_pft() {
+        let markdown = "```rust\nfn main() {}\n```";
+        let gemtext = markdown_to_gemtext(markdown);
+        assert_eq!(gemtext, "```rust\nfn main() {}\n```\n");
+    }
+
+    #[test]
+    fn gemtext_converts_to_markdown() {
+        let raw_text =
+            "\
+            # Heading\n\
+            Some text here\n\
+            => gemini://example.org Example\n\
+            * one\n\
+            * two\n\
+            > quote\n";
+        let markdown = gemtext_to_markdown(&parse_gemtext(raw_text));
+        assert_eq!(markdown,
+            "# Heading\n\n\
+            Some text here\n\n\
+            [Example](<gemini://example.org>)\n\n\
+            * one\n\
+            * two\n\n\
+            > quote\n");
+    }
+
+    #[test]
+    fn gemtext_text_that_looks_like_markdown_is_escaped() {
+        let raw_text = "1. not a list\n- not a list either\n";
+        let markdown = gemtext_to_markdown(&parse_gemtext(raw_text));
+        assert_eq!(markdown, "1\\. not a list\n\n\\- not a list either\n");
+    }
+
+    #[test]
+    fn gemtext_survives_a_round_trip_through_markdown() {
+        let raw_text = "\
+            # C# and *stars* #\n\
+            Some _under_ `code` [brackets] <b>html</b> & back\\slash\n\
+            1) not a list\n\
+            \x20   indented, not code\n\
+            => gemini://example.org/a(b) c d Label with ]brackets[\n\
+            => gemini://example.org/x>y\n\
+            * item with *emphasis*\n\
+            > quote with > inside\n";
+        let markdown = gemtext_to_markdown(&parse_gemtext(raw_text));
+        assert!(markdown.contains("[c d Label with \\]brackets\\[](<gemini://example.org/a(b)>)"));
+        assert!(markdown.contains("\n\nindented, not code\n\n"));
+        let spaced = GemtextToken {
+            kind: TokenKind::Link,
+            data: "my file (1).gmi".to_owned(),
+            extra: String::new(),
+        };
+        assert_eq!(gemtext_to_markdown(&[spaced]), "[my file (1).gmi](<my file (1).gmi>)\n");
+        // Blocks come back separated by blank lines, and leading spaces are
+        // dropped, but the text is otherwise the same.
+        assert_eq!(markdown_to_gemtext(&markdown), "\
+            # C# and *stars* #\n\n\
+            Some _under_ `code` [brackets] <b>html</b> & back\\slash\n\n\
+            1) not a list\n\n\
+            indented, not code\n\n\
+            => gemini://example.org/a(b) c d Label with ]brackets[\n\n\
+            => gemini://example.org/x>y\n\n\
+            * item with *emphasis*\n\n\
+            > quote with > inside\n");
+    }
+}
@@ -0,0 +1,278 @@
+use std::env;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+
+use crate::gemtext::{parse_body, GemtextToken, TokenKind};
+use crate::image_art::{to_256_colors, ColorDepth};
+use crate::layout::{layout_gemtext, LayoutOptions};
+use crate::links::{page_links, LinkKind, PageLink};
+use crate::settings::{load_settings, load_theme};
+use crate::theme::{Element, ElementStyle, Theme, ThemeColor, ThemeEffect};
+
+// Width to wrap at when $COLUMNS doesn't say.
+const DEFAULT_COLUMNS: usize = 80;
+
+// How wide output to the terminal should be, from $COLUMNS.
+pub fn output_width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.trim().parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+// Links show their URL after their name, since there's nothing to select
+// them with outside the browser.
+fn with_link_urls(chain: &[GemtextToken]) -> Vec<GemtextToken> {
+    chain
+        .iter()
+        .map(|token| {
+            let name = token.extra.trim();
+            if token.kind == TokenKind::Link && !name.is_empty() && name != token.data.trim() {
+                GemtextToken {
+                    kind: token.kind,
+                    data: token.data.clone(),
+                    extra: format!("{} <{}>", name, token.data.trim()),
+                }
+            } else {
+                token.clone()
+            }
+        })
+        .collect()
+}
+
+// Links get the same markers as in the browser. Outside of it there's no
+// page to resolve relative links against, so those are taken to stay on the
+// capsule.
+fn layout_options(links: &[PageLink], width: usize, highlight: bool) -> LayoutOptions {
+    LayoutOptions {
+        width,
+        bidi: true,
+        highlight,
+        markers: links
+            .iter()
+            .map(|link| {
+                let kind = if link.url.is_some() { link.kind } else { LinkKind::Gemini };
+                (link.token, kind.marker())
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+// Lays a page out as plain text, wrapped at width cells (0 for no wrapping)
+// the way the browser would show it.
+pub fn render_text(chain: &[GemtextToken], width: usize) -> String {
+    let chain = with_link_urls(chain);
+    let links = page_links(&chain, None, |_| false);
+    layout_gemtext(&chain, &layout_options(&links, width, false))
+        .iter()
+        .map(|line| format!("{}\n", line.text.trim_end()))
+        .collect()
+}
+
+fn color_code(color: ThemeColor, background: bool, depth: ColorDepth) -> String {
+    let base = if background { 40 } else { 30 };
+    match color {
+        ThemeColor::Default => (base + 9).to_string(),
+        ThemeColor::Dark(i) => (base + i as u32).to_string(),
+        ThemeColor::Light(i) => (base + 60 + i as u32).to_string(),
+        ThemeColor::Rgb(r, g, b) => match depth {
+            ColorDepth::TrueColor => format!("{};2;{};{};{}", base + 8, r, g, b),
+            ColorDepth::Palette256 => format!("{};5;{}", base + 8, to_256_colors([r, g, b])),
+        },
+    }
+}
+
+// The escape sequence that switches to style, or nothing for an empty one.
+fn style_sequence(style: &ElementStyle, depth: ColorDepth) -> String {
+    let mut codes = Vec::new();
+    if let Some(fg) = style.fg {
+        codes.push(color_code(fg, false, depth));
+    }
+    if let Some(bg) = style.bg {
+        codes.push(color_code(bg, true, depth));
+    }
+    for effect in &style.effects {
+        codes.push(match effect {
+            ThemeEffect::Bold => "1",
+            ThemeEffect::Italic => "3",
+            ThemeEffect::Underline => "4",
+            ThemeEffect::Reverse => "7",
+            ThemeEffect::Strikethrough => "9",
+        }.to_owned());
+    }
+    if codes.is_empty() {
+        String::new()
+    } else {
+        format!("\x1b[{}m", codes.join(";"))
+    }
+}
+
+fn styled(text: &str, style: &ElementStyle, depth: ColorDepth) -> String {
+    let start = style_sequence(style, depth);
+    if start.is_empty() || text.is_empty() {
+        text.to_owned()
+    } else {
+        format!("{}{}\x1b[0m", start, text)
+    }
+}
+
+// Lays a page out like render_text, styled with theme's colors and effects
+// as ANSI escape sequences. Only each element's own style is used, so the
+// page sits on the terminal's background rather than the theme's.
+pub fn render_ansi(chain: &[GemtextToken], width: usize, theme: &Theme, depth: ColorDepth,
+                   highlight: bool) -> String {
+    let chain = with_link_urls(chain);
+    let links = page_links(&chain, None, |_| false);
+    let mut output = String::new();
+    for line in layout_gemtext(&chain, &layout_options(&links, width, highlight)) {
+        let element = match links.iter().find(|link| link.token == line.token) {
+            Some(link) if link.url.is_some() && link.kind.is_external() => Element::ExternalLink,
+            _ => Element::for_token(line.kind),
+        };
+        let style = theme.style(element);
+        if line.spans.is_empty() {
+            // Indents are left unstyled so underlines start at the text.
+            let text = line.text.trim_end();
+            let indent = text.len() - text.trim_start().len();
+            output.push_str(&text[..indent]);
+            output.push_str(&styled(&text[indent..], &style, depth));
+        } else {
+            let mut printed = 0;
+            for span in &line.spans {
+                output.push_str(&styled(&line.text[printed..span.start], &style, depth));
+                let span_style = theme.style(Element::for_highlight(span.kind));
+                output.push_str(&styled(&line.text[span.start..span.end], &span_style, depth));
+                printed = span.end;
+            }
+            output.push_str(&styled(line.text[printed..].trim_end(), &style, depth));
+        }
+        output.push('\n');
+    }
+    output
+}
+
+// What armstrong render was asked to do.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RenderOptions {
+    // Files to render, with "-" or none at all for stdin.
+    pub files: Vec<String>,
+    // The config file to use, empty for the usual one.
+    pub config: String,
+    // A theme to use instead of the one in the config file.
+    pub theme: Option<String>,
+}
+
+// Files are taken to be gemtext unless their extension says otherwise.
+fn mimetype_for(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("md") | Some("markdown") => "text/markdown",
+        Some("txt") => "text/plain",
+        _ => "text/gemini",
+    }
+}
+
+fn read_input(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut input = String::new();
+        io::stdin().read_to_string(&mut input)?;
+        Ok(input)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+// Runs armstrong render and returns what to exit with. Pages are styled
+// only when going to a terminal, so pipes and files get plain text.
+pub fn run_render(options: &RenderOptions) -> i32 {
+    let mut settings = match load_settings(&options.config) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    if let Some(theme) = &options.theme {
+        settings.theme.name = theme.clone();
+    }
+    let theme = match load_theme(&settings.theme) {
+        Ok(theme) => theme,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let depth = ColorDepth::from_setting(&settings.display.image_colors);
+    let styled = io::stdout().is_terminal();
+    let width = output_width();
+    let files = if options.files.is_empty() { vec!["-".to_owned()] } else { options.files.clone() };
+
+    let mut status = 0;
+    for file in &files {
+        let input = match read_input(file) {
+            Ok(input) => input,
+            Err(e) => {
+                eprintln!("armstrong: {}: {}", file, e);
+                status = 1;
+                continue;
+            }
+        };
+        let chain = parse_body(mimetype_for(file), &input);
+        let output = if styled {
+            render_ansi(&chain, width, &theme, depth, settings.display.syntax_highlighting)
+        } else {
+            render_text(&chain, width)
+        };
+        if let Err(e) = io::stdout().lock().write_all(output.as_bytes()) {
+            // A reader that stops early, like head, isn't a failure.
+            if e.kind() != io::ErrorKind::BrokenPipe {
+                eprintln!("armstrong: {}", e);
+                status = 1;
+            }
+            break;
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn text_is_wrapped_with_link_urls() {
+        let chain = parse_gemtext("# Title\n=> /a An example link\n=> /b\n* one two three\n");
+        assert_eq!(render_text(&chain, 14), "\
+TITLE
+→ An example
+  link </a>
+→ /b
+• one two
+  three
+");
+    }
+
+    #[test]
+    fn ansi_output_uses_the_theme() {
+        let theme = Theme::builtin("basic").unwrap();
+        let chain = parse_gemtext("# Title\n=> https://example.org Web\n> quoted\nplain\n");
+        assert_eq!(render_ansi(&chain, 80, &theme, ColorDepth::Palette256, false), "\
+\x1b[1;4mTITLE\x1b[0m
+\x1b[36;4m⇗ Web <https://example.org>\x1b[0m
+\x1b[90;3m> quoted\x1b[0m
+plain
+");
+        let dark = Theme::builtin("dark").unwrap();
+        let chain = parse_gemtext("=> /a\n");
+        assert_eq!(render_ansi(&chain, 80, &dark, ColorDepth::TrueColor, false),
+                   "\x1b[38;2;95;175;255;4m→ /a\x1b[0m\n");
+        assert_eq!(render_ansi(&chain, 80, &dark, ColorDepth::Palette256, false),
+                   "\x1b[38;5;75;4m→ /a\x1b[0m\n");
+    }
+}
@@ -0,0 +1,220 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use crate::layout::LayoutLine;
+use crate::settings::SearchSettings;
+
+// A match on a laid out page, as a byte range of each row's text it covers.
+// Only a match in a line that was wrapped can cover more than one row.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchMatch {
+    pub spans: Vec<MatchSpan>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchSpan {
+    pub row: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl SearchMatch {
+    // The row the match starts on.
+    pub fn row(&self) -> usize {
+        self.spans[0].row
+    }
+
+    // The row the match ends on.
+    pub fn last_row(&self) -> usize {
+        self.spans[self.spans.len() - 1].row
+    }
+}
+
+// Handles search patterns that aren't valid regular expressions.
+#[derive(Clone, Debug)]
+pub struct SearchError {
+    details: String,
+}
+
+impl std::fmt::Display for SearchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Invalid pattern: {}", self.details)
+    }
+}
+
+// Builds the regex for a query. Plain text queries are escaped so they match
+// literally.
+fn search_regex(query: &str, options: &SearchSettings) -> Result<Regex, SearchError> {
+    let pattern = if options.regex {
+        query.to_owned()
+    } else {
+        regex::escape(query)
+    };
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!options.case_sensitive)
+        .build()
+        .map_err(|e| {
+            // Parse errors span several lines, pointing at where the pattern
+            // went wrong, with the actual problem on the last one.
+            let message = e.to_string();
+            let last = message.lines().last().unwrap_or_default();
+            SearchError {
+                details: last.trim_start_matches("error: ").to_owned(),
+            }
+        })
+}
+
+// The part of the text searched for a line that one row shows, which is
+// the bytes from start to end of the row's text.
+struct Segment {
+    // Where the part starts in the searched text.
+    offset: usize,
+    row: usize,
+    start: usize,
+    end: usize,
+}
+
+// Joins the rows wrapped from one line back into the text they were cut
+// from, putting back the spaces dropped between them.
+fn join_rows(lines: &[LayoutLine], rows: Range<usize>) -> (String, Vec<Segment>) {
+    let mut text = String::new();
+    let mut segments = Vec::new();
+    for row in rows {
+        let line = &lines[row];
+        let start = line.source.as_ref().map_or(0, |source| {
+            if source.space_before && !text.is_empty() {
+                text.push(' ');
+            }
+            source.start
+        });
+        segments.push(Segment {
+            offset: text.len(),
+            row,
+            start,
+            end: line.text.len(),
+        });
+        text.push_str(&line.text[start..]);
+    }
+    (text, segments)
+}
+
+// The parts of each row covered by the bytes from start to end of the text
+// joined from segments.
+fn spans_between(segments: &[Segment], start: usize, end: usize) -> Vec<MatchSpan> {
+    segments
+        .iter()
+        .filter_map(|segment| {
+            let from = start.max(segment.offset);
+            let to = end.min(segment.offset + segment.end - segment.start);
+            (from < to).then(|| MatchSpan {
+                row: segment.row,
+                start: segment.start + from - segment.offset,
+                end: segment.start + to - segment.offset,
+            })
+        })
+        .collect()
+}
+
+// Finds every match of query in the rows of a page, top to bottom. Rows are
+// searched as they're shown, so link labels and preformatted blocks are
+// included but markers aren't, and the rows a line was wrapped into are
+// searched together so a match can go on from one to the next.
+pub fn find_matches(lines: &[LayoutLine], query: &str, options: &SearchSettings)
+    -> Result<Vec<SearchMatch>, SearchError> {
+    if query.is_empty() {
+        return Ok(Vec::new());
+    }
+    let regex = search_regex(query, options)?;
+    let mut matches = Vec::new();
+    let mut first = 0;
+    while first < lines.len() {
+        // A line's rows follow on from one another for as long as they're
+        // from the same token and can be read back in order.
+        let mut last = first + 1;
+        if lines[first].source.is_some() {
+            let token = lines[first].token;
+            while lines.get(last).is_some_and(|line| line.token == token && line.source.is_some()) {
+                last += 1;
+            }
+        }
+        let (text, segments) = join_rows(lines, first..last);
+        for found in regex.find_iter(&text) {
+            let spans = spans_between(&segments, found.start(), found.end());
+            // Patterns like "a*" match nothing everywhere, which isn't
+            // useful, and neither is a match of only a dropped space.
+            if !spans.is_empty() {
+                matches.push(SearchMatch { spans });
+            }
+        }
+        first = last;
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+    use crate::layout::{layout_gemtext, LayoutOptions};
+
+    fn lines(text: &str) -> Vec<LayoutLine> {
+        layout_gemtext(&parse_gemtext(text), &LayoutOptions::default())
+    }
+
+    #[test]
+    fn search_covers_links_and_preformatted_text() {
+        let lines = lines("=> gemini://example.org/ Cat pictures\n```\ncat file\n```\nno match\n");
+        let options = SearchSettings::default();
+        let matches = find_matches(&lines, "CAT", &options).unwrap();
+        let rows: Vec<usize> = matches.iter().map(|m| m.row()).collect();
+        assert_eq!(rows, vec![0, 1]);
+        let span = &matches[0].spans[0];
+        assert_eq!(&lines[0].text[span.start..span.end], "Cat");
+    }
+
+    #[test]
+    fn case_and_regex_options_apply() {
+        let lines = lines("Cat cat c.t\n");
+        let mut options = SearchSettings {
+            case_sensitive: true,
+            regex: false,
+        };
+        assert_eq!(find_matches(&lines, "cat", &options).unwrap().len(), 1);
+        assert_eq!(find_matches(&lines, "c.t", &options).unwrap().len(), 1);
+        options.regex = true;
+        assert_eq!(find_matches(&lines, "c.t", &options).unwrap().len(), 2);
+        let error = find_matches(&lines, "(", &options).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid pattern: unclosed group");
+        assert!(find_matches(&lines, "x*", &options).unwrap().is_empty());
+    }
+
+    #[test]
+    fn matches_go_on_across_wrapped_rows() {
+        let options = LayoutOptions {
+            width: 12,
+            ..LayoutOptions::default()
+        };
+        let lines = layout_gemtext(&parse_gemtext("* one two three
+* abcdefghijklmnop
+"), &options);
+        let text: Vec<&str> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(text, ["• one two", "  three", "• abcdefghij", "  klmnop"]);
+
+        let settings = SearchSettings::default();
+        let matches = find_matches(&lines, "TWO three", &settings).unwrap();
+        assert_eq!(matches, [SearchMatch {
+            spans: vec![
+                MatchSpan { row: 0, start: 8, end: 11 },
+                MatchSpan { row: 1, start: 2, end: 7 },
+            ],
+        }]);
+        // Words broken to fit are joined back up without a space.
+        let matches = find_matches(&lines, "jk", &settings).unwrap();
+        assert_eq!(matches[0].spans.len(), 2);
+        assert_eq!((matches[0].row(), matches[0].last_row()), (2, 3));
+        // Only the space within a row is found, not the one put back between
+        // rows or those in markers and indents.
+        assert_eq!(find_matches(&lines, " ", &settings).unwrap().len(), 1);
+    }
+}
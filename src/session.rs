@@ -0,0 +1,87 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+// A tab as it's saved between runs: its page, the pages it can go back and
+// forward to, and the row the page was scrolled to.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct TabSession {
+    pub title: String,
+    pub url: String,
+    pub scroll: usize,
+    pub back: Vec<String>,
+    pub forward: Vec<String>,
+}
+
+// The tabs open when armstrong quit, kept in the data directory as TOML.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Session {
+    // Index into tabs of the tab that was being shown.
+    pub current_tab: usize,
+    pub tabs: Vec<TabSession>,
+}
+
+impl Session {
+    // Reads the session saved at path. A missing or damaged file gives an
+    // empty session, which restores nothing.
+    pub fn load(path: &Path) -> Session {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| toml::from_str(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    // Writes the session to path. It goes to a temporary file first, so
+    // quitting or crashing halfway through never leaves half a session.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents)?;
+        fs::rename(&temp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_file_round_trips() {
+        let path = Path::new("/tmp/armstrong_session_test.toml");
+        let _ = fs::remove_file(path);
+        assert_eq!(Session::load(path), Session::default());
+
+        let session = Session {
+            current_tab: 1,
+            tabs: vec![
+                TabSession {
+                    title: "Home".to_owned(),
+                    url: "gemini://example.org/".to_owned(),
+                    scroll: 0,
+                    back: Vec::new(),
+                    forward: vec!["gemini://example.org/next".to_owned()],
+                },
+                TabSession {
+                    title: "Help".to_owned(),
+                    url: "about:help".to_owned(),
+                    scroll: 42,
+                    back: vec!["gemini://example.org/".to_owned()],
+                    forward: Vec::new(),
+                },
+            ],
+        };
+        session.save(path).unwrap();
+        assert_eq!(Session::load(path), session);
+
+        fs::write(path, "tabs = 3").unwrap();
+        assert_eq!(Session::load(path), Session::default());
+        let _ = fs::remove_file(path);
+    }
+}
@@ -1,44 +1,493 @@
 // The default armstrong config file. This file is copied to
-// $XDG_HOME_CONFIG/armstrong/config.toml if the file does not already exist or
-// $HOME/.config/armstrong/config.toml if $XDG_HOME_CONFIG is unset.
-const DEFAULT_CONFIG_TOML: &str = r#"
+// $XDG_CONFIG_HOME/armstrong/config.toml if the file does not already exist or
+// $HOME/.config/armstrong/config.toml if $XDG_CONFIG_HOME is unset.
+const DEFAULT_CONFIG_TOML: &str = r##"
+[general]
+# Where words typed into the address bar are searched for. They're sent as
+# the query, the way any capsule asking for input gets its answer.
+search_url = "gemini://geminispace.info/search"
+# The page opened at startup, a URL or an about: page like about:help. Left
+# empty, the address bar is opened instead.
+home = ""
+# Whether to reopen the tabs that were open when armstrong last quit, each
+# with its history and where it was scrolled to.
+restore_session = false
+
 [downloads]
 download_dir = "$HOME/Downloads/"
-"#;
 
+[handlers]
+# Programs that open responses which can't be shown as a page, by MIME type.
+# A type like image/* covers the whole family and * covers anything else.
+# The file replaces %f in the command, or goes on the end without one.
+# Arguments with spaces can be quoted as in a shell, but the command isn't
+# run by a shell, so there are no variables, pipes or redirections.
+# Programs that draw in the terminal need terminal = true, so the browser
+# steps aside while they run.
+"image/*" = { command = "xdg-open" }
+"audio/*" = { command = "mpv --no-video %f", terminal = true }
+"video/*" = { command = "xdg-open" }
+"application/pdf" = { command = "xdg-open" }
+
+[display]
+# Column to wrap text at, 0 wraps to the width of the terminal.
+wrap_width = 0
+# Reorder right-to-left text such as Hebrew and Arabic for display.
+bidi = true
+# Highlight preformatted blocks whose alt text names a language, like rust.
+syntax_highlighting = true
+# Show PNG, JPEG and GIF images in the page, drawn with half block
+# characters, instead of asking what to open them with.
+inline_images = true
+# Colors for images: truecolor, 256 or auto, which asks $COLORTERM.
+image_colors = "auto"
+
+[search]
+# Searches ignore case unless this is set. Ctrl-t toggles it while searching.
+case_sensitive = false
+# Treat searches as regular expressions. Ctrl-r toggles it while searching.
+regex = false
+
+[keys]
+# The bindings to start from: default, vim or emacs.
+preset = "default"
+
+[keys.bindings]
+# Actions can be rebound on top of the preset. Each takes a list of key
+# sequences written the way vim writes keys, for example:
+#   goto = ["o", "<C-l>"]
+#   top = ["gg"]
+#   quit = ["<C-x><C-c>"]
+# The actions are goto, back, forward, reload, new_tab, close_tab, next_tab,
+# prev_tab, follow_hint, follow_hint_new_tab, next_link, prev_link,
+# follow_link, search, next_match, prev_match, outline, next_heading,
+# prev_heading, bookmark, save_as, view_source, copy_source, save_source,
+# scroll_down, scroll_up, scroll_left, scroll_right, page_down, page_up, top,
+# bottom, command_line, theme and quit. Each of them is also a command on the
+# command line.
+
+[theme]
+# The built in themes are dark, light and basic, which sticks to the 16
+# standard terminal colors. Single elements can be restyled on top of the
+# chosen theme in [theme.styles], for example:
+#   link = { fg = "#5f87ff", effects = ["underline"] }
+#   heading = { fg = "light yellow", effects = ["bold"] }
+name = "dark"
+
+# Client certificates, or identities, to send to capsules that ask for one.
+# Each is sent to the URLs starting with one of its urls. One can be made with
+#   openssl req -x509 -newkey rsa:2048 -nodes -days 3650 -subj /CN=me \
+#       -keyout me.key -out me.crt
+# [[identities]]
+# name = "me"
+# cert = "$HOME/.config/armstrong/me.crt"
+# key = "$HOME/.config/armstrong/me.key"
+# urls = ["gemini://example.org/app/"]
+"##;
+
+use std::collections::HashMap;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::keymap::{parse_keys, Action, Keymap, PRESETS};
+use crate::theme::{Element, ElementStyle, Theme, BUILTIN_THEMES};
+
+// The settings that can be changed while browsing with :set.
+pub const SETTING_KEYS: [&str; 13] = [
+    "general.search_url",
+    "general.home",
+    "general.restore_session",
+    "downloads.download_dir",
+    "display.wrap_width",
+    "display.bidi",
+    "display.syntax_highlighting",
+    "display.inline_images",
+    "display.image_colors",
+    "search.case_sensitive",
+    "search.regex",
+    "keys.preset",
+    "theme.name",
+];
+
+// Settings loaded from config.toml. Anything missing from the file falls back
+// to the values in DEFAULT_CONFIG_TOML.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub general: GeneralSettings,
+    pub downloads: DownloadSettings,
+    // Programs keyed by the MIME types they open.
+    pub handlers: HashMap<String, HandlerSettings>,
+    pub display: DisplaySettings,
+    pub search: SearchSettings,
+    pub keys: KeySettings,
+    pub theme: ThemeSettings,
+    pub identities: Vec<IdentitySettings>,
+}
 
-// use cursive::theme::{Color, Palette, Theme};
+impl Default for Settings {
+    fn default() -> Self {
+        let handler = |command: &str, terminal| HandlerSettings {
+            command: command.to_owned(),
+            terminal,
+        };
+        let handlers = [
+            ("image/*", handler("xdg-open", false)),
+            ("audio/*", handler("mpv --no-video %f", true)),
+            ("video/*", handler("xdg-open", false)),
+            ("application/pdf", handler("xdg-open", false)),
+        ];
+        Settings {
+            general: GeneralSettings::default(),
+            downloads: DownloadSettings::default(),
+            handlers: handlers
+                .into_iter()
+                .map(|(pattern, handler)| (pattern.to_owned(), handler))
+                .collect(),
+            display: DisplaySettings::default(),
+            search: SearchSettings::default(),
+            keys: KeySettings::default(),
+            theme: ThemeSettings::default(),
+            identities: Vec::new(),
+        }
+    }
+}
 
-pub fn create_config_file(override_path: &str) {
-    let config_path: &Path;
-    let config_path_string: String;
+fn parse_bool(key: &str, value: &str) -> Result<bool, SettingsError> {
+    match value {
+        "true" | "on" | "yes" => Ok(true),
+        "false" | "off" | "no" => Ok(false),
+        _ => Err(SettingsError::new(&format!(
+                    "{} must be true or false, not \"{}\"", key, value))),
+    }
+}
 
-    if override_path.is_empty() {
-        let home_dir = env::var("HOME").unwrap();
-        config_path_string = format!("{}/.config/armstrong/config.toml", 
-                                    home_dir);
-        config_path = Path::new(&config_path_string);
+impl Settings {
+    // The current value of one of SETTING_KEYS, written the way :set takes
+    // it.
+    pub fn get(&self, key: &str) -> Option<String> {
+        Some(match key {
+            "general.search_url" => self.general.search_url.clone(),
+            "general.home" => self.general.home.clone(),
+            "general.restore_session" => self.general.restore_session.to_string(),
+            "downloads.download_dir" => self.downloads.download_dir.clone(),
+            "display.wrap_width" => self.display.wrap_width.to_string(),
+            "display.bidi" => self.display.bidi.to_string(),
+            "display.syntax_highlighting" => self.display.syntax_highlighting.to_string(),
+            "display.inline_images" => self.display.inline_images.to_string(),
+            "display.image_colors" => self.display.image_colors.clone(),
+            "search.case_sensitive" => self.search.case_sensitive.to_string(),
+            "search.regex" => self.search.regex.to_string(),
+            "keys.preset" => self.keys.preset.clone(),
+            "theme.name" => self.theme.name.clone(),
+            _ => return None,
+        })
+    }
+
+    // Changes one of SETTING_KEYS, parsing value the way config.toml would.
+    // Only the value is checked here; a theme or preset that doesn't exist
+    // is caught when it's loaded.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        let value = value.trim().trim_matches('"');
+        match key {
+            "general.search_url" => self.general.search_url = value.to_owned(),
+            "general.home" => self.general.home = value.to_owned(),
+            "general.restore_session" => self.general.restore_session = parse_bool(key, value)?,
+            "downloads.download_dir" => self.downloads.download_dir = value.to_owned(),
+            "display.wrap_width" => {
+                self.display.wrap_width = value.parse().map_err(|_| SettingsError::new(
+                        &format!("{} must be a number, not \"{}\"", key, value)))?;
+            },
+            "display.bidi" => self.display.bidi = parse_bool(key, value)?,
+            "display.syntax_highlighting" => {
+                self.display.syntax_highlighting = parse_bool(key, value)?;
+            },
+            "display.inline_images" => self.display.inline_images = parse_bool(key, value)?,
+            "display.image_colors" => match value {
+                "auto" | "truecolor" | "256" => self.display.image_colors = value.to_owned(),
+                _ => return Err(SettingsError::new(&format!(
+                            "{} must be auto, truecolor or 256, not \"{}\"", key, value))),
+            },
+            "search.case_sensitive" => self.search.case_sensitive = parse_bool(key, value)?,
+            "search.regex" => self.search.regex = parse_bool(key, value)?,
+            "keys.preset" => self.keys.preset = value.to_owned(),
+            "theme.name" => self.theme.name = value.to_owned(),
+            _ => return Err(SettingsError::new(&format!("unknown setting \"{}\"", key))),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct GeneralSettings {
+    pub search_url: String,
+    pub home: String,
+    pub restore_session: bool,
+}
+
+impl Default for GeneralSettings {
+    fn default() -> Self {
+        GeneralSettings {
+            search_url: "gemini://geminispace.info/search".to_owned(),
+            home: String::new(),
+            restore_session: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DownloadSettings {
+    pub download_dir: String,
+}
+
+// Expands $HOME, or a leading ~, in a path from the config file.
+pub fn expand_home(path: &str) -> PathBuf {
+    let home = env::var("HOME").unwrap_or_default();
+    let path = path.replace("$HOME", &home);
+    match path.strip_prefix('~') {
+        Some(rest) => PathBuf::from(format!("{}{}", home, rest)),
+        None => PathBuf::from(path),
+    }
+}
+
+impl DownloadSettings {
+    pub fn download_dir(&self) -> PathBuf {
+        expand_home(&self.download_dir)
+    }
+}
+
+impl Default for DownloadSettings {
+    fn default() -> Self {
+        DownloadSettings {
+            download_dir: "$HOME/Downloads/".to_owned(),
+        }
+    }
+}
+
+// An external program that opens some kind of response.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct HandlerSettings {
+    pub command: String,
+    // Whether the program runs in the terminal rather than a window of its
+    // own.
+    pub terminal: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DisplaySettings {
+    pub wrap_width: usize,
+    pub bidi: bool,
+    pub syntax_highlighting: bool,
+    pub inline_images: bool,
+    // truecolor, 256 or auto.
+    pub image_colors: String,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        DisplaySettings {
+            wrap_width: 0,
+            bidi: true,
+            syntax_highlighting: true,
+            inline_images: true,
+            image_colors: "auto".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    pub case_sensitive: bool,
+    pub regex: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct KeySettings {
+    pub preset: String,
+    // Key sequences keyed by Action::name(), replacing the preset's.
+    pub bindings: HashMap<String, Vec<String>>,
+}
+
+impl Default for KeySettings {
+    fn default() -> Self {
+        KeySettings {
+            preset: "default".to_owned(),
+            bindings: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ThemeSettings {
+    pub name: String,
+    // Per element overrides keyed by Element::name().
+    pub styles: HashMap<String, ElementStyle>,
+}
+
+impl Default for ThemeSettings {
+    fn default() -> Self {
+        ThemeSettings {
+            name: "dark".to_owned(),
+            styles: HashMap::new(),
+        }
+    }
+}
+
+// A client certificate and the URLs it's sent to.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct IdentitySettings {
+    pub name: String,
+    // Paths to the PEM encoded certificate and private key.
+    pub cert: String,
+    pub key: String,
+    // URL prefixes the identity is used for.
+    pub urls: Vec<String>,
+}
+
+// Handles errors while reading or parsing the config file.
+#[derive(Clone, Debug)]
+pub struct SettingsError {
+    details: String,
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Couldn't load config: {}", self.details)
+    }
+}
+
+impl SettingsError {
+    fn new(message: &str) -> SettingsError {
+        SettingsError {
+            details: message.to_owned(),
+        }
+    }
+}
+
+// Returns where the config file lives when no override is given.
+pub fn default_config_path() -> PathBuf {
+    let config_dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home_dir = env::var("HOME").unwrap_or_default();
+            Path::new(&home_dir).join(".config")
+        }
+    };
+    config_dir.join("armstrong").join("config.toml")
+}
+
+// Returns where armstrong keeps its data, such as history, following
+// $XDG_DATA_HOME the same way the config follows $XDG_CONFIG_HOME.
+pub fn default_data_dir() -> PathBuf {
+    let data_dir = match env::var("XDG_DATA_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => {
+            let home_dir = env::var("HOME").unwrap_or_default();
+            Path::new(&home_dir).join(".local").join("share")
+        }
+    };
+    data_dir.join("armstrong")
+}
+
+pub fn create_config_file(override_path: &str) -> Result<(), SettingsError> {
+    let config_path = if override_path.is_empty() {
+        default_config_path()
+    } else {
+        PathBuf::from(override_path)
+    };
+
+    if let Some(parent) = config_path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    File::create(&config_path)
+        .and_then(|mut file| file.write_all(DEFAULT_CONFIG_TOML.as_bytes()))
+        .map_err(|e| SettingsError::new(
+                &format!("Couldn't write to {}: {}", config_path.display(), e)))
+}
+
+// Reads config.toml, creating it with the defaults first if it doesn't exist
+// yet. A config file asked for by name has to exist already.
+pub fn load_settings(override_path: &str) -> Result<Settings, SettingsError> {
+    let config_path = if override_path.is_empty() {
+        default_config_path()
     } else {
-        config_path = Path::new(override_path);
+        PathBuf::from(override_path)
+    };
+
+    if override_path.is_empty() && !config_path.exists() {
+        create_config_file(&config_path.to_string_lossy())?;
     }
+    let contents = match fs::read_to_string(&config_path) {
+        Ok(contents) => contents,
+        Err(e) => return Err(SettingsError::new(
+                    &format!("{}: {}", config_path.display(), e))),
+    };
+    parse_settings(&contents)
+}
 
-    let mut file = File::create(&config_path).unwrap();
-    match file.write(DEFAULT_CONFIG_TOML.as_bytes()) {
-        Ok(_) => (),
-        Err(e) => panic!("Couldn't write to {}: {}",
-                    config_path.display(),
-                    e),
-    }                    
+pub fn parse_settings(contents: &str) -> Result<Settings, SettingsError> {
+    match toml::from_str(contents) {
+        Ok(settings) => Ok(settings),
+        Err(e) => Err(SettingsError::new(&e.to_string())),
+    }
 }
 
-// pub fn load_theme() -> Theme {
-//     let mut palette = Palette::default();
-//     palette.set_color
-// }
+// Builds the theme named in the [theme] section with its style overrides
+// applied on top.
+pub fn load_theme(settings: &ThemeSettings) -> Result<Theme, SettingsError> {
+    let mut theme = match Theme::builtin(&settings.name) {
+        Some(theme) => theme,
+        None => return Err(SettingsError::new(&format!(
+                    "unknown theme \"{}\", expected one of {}",
+                    settings.name,
+                    BUILTIN_THEMES.join(", ")))),
+    };
+    for (name, style) in &settings.styles {
+        match Element::from_name(name) {
+            Some(element) => theme.set_style(element, style.clone()),
+            None => return Err(SettingsError::new(&format!(
+                        "unknown theme element \"{}\"", name))),
+        }
+    }
+    Ok(theme)
+}
+
+// Builds the keymap from the chosen preset and any rebound actions.
+pub fn load_keymap(settings: &KeySettings) -> Result<Keymap, SettingsError> {
+    let mut keymap = match Keymap::preset(&settings.preset) {
+        Some(keymap) => keymap,
+        None => return Err(SettingsError::new(&format!(
+                    "unknown key preset \"{}\", expected one of {}",
+                    settings.preset,
+                    PRESETS.join(", ")))),
+    };
+    for (name, sequences) in &settings.bindings {
+        let action = match Action::from_name(name) {
+            Some(action) => action,
+            None => return Err(SettingsError::new(&format!(
+                        "unknown action \"{}\"", name))),
+        };
+        keymap.unbind(action);
+        for keys in sequences {
+            let keys = parse_keys(keys)
+                .map_err(|e| SettingsError::new(&format!("{} for {}", e, name)))?;
+            keymap.bind(keys, action);
+        }
+    }
+    Ok(keymap)
+}
 
 #[cfg(test)]
 mod tests {
@@ -47,10 +496,79 @@ mod tests {
 
     #[test]
     fn create_config_file_works() {
-        create_config_file("/tmp/config.toml");
+        create_config_file("/tmp/config.toml").unwrap();
         let mut config = File::open("/tmp/config.toml").unwrap();
         let mut s = String::new();
         config.read_to_string(&mut s).expect("Couldn't open file.");
         assert_eq!(s, DEFAULT_CONFIG_TOML);
     }
+
+    #[test]
+    fn default_config_parses() {
+        let settings = parse_settings(DEFAULT_CONFIG_TOML).unwrap();
+        assert_eq!(settings.downloads.download_dir, "$HOME/Downloads/");
+        assert_eq!(settings.display.wrap_width, 0);
+        assert!(settings.display.bidi);
+        assert!(settings.display.syntax_highlighting);
+        assert_eq!(settings.handlers, Settings::default().handlers);
+    }
+
+    #[test]
+    fn theme_overrides_apply_on_top_of_builtin() {
+        let settings = parse_settings(
+            "[theme]\n\
+            name = \"light\"\n\
+            [theme.styles]\n\
+            link = { fg = \"red\", effects = [\"bold\"] }\n").unwrap();
+        let theme = load_theme(&settings.theme).unwrap();
+        let link = theme.style(Element::Link);
+        assert_eq!(theme.name, "light");
+        assert_eq!(link.fg, Some(crate::theme::ThemeColor::Dark(1)));
+        assert_eq!(link.effects, vec![crate::theme::ThemeEffect::Bold]);
+
+        let settings = parse_settings("[theme.styles]\nlnk = {}\n").unwrap();
+        assert!(load_theme(&settings.theme).is_err());
+    }
+
+    #[test]
+    fn key_bindings_replace_the_preset() {
+        let settings = parse_settings(
+            "[keys]\n\
+            preset = \"vim\"\n\
+            [keys.bindings]\n\
+            top = [\"<Home>\", \"gg\"]\n\
+            quit = []\n").unwrap();
+        let keymap = load_keymap(&settings.keys).unwrap();
+        assert_eq!(keymap.keys_for(Action::Top).len(), 2);
+        assert!(keymap.keys_for(Action::Quit).is_empty());
+        assert_eq!(keymap.keys_for(Action::Back), vec![&parse_keys("H").unwrap()[..]]);
+
+        let settings = parse_settings("[keys.bindings]\ntop = [\"<Hom>\"]\n").unwrap();
+        assert!(load_keymap(&settings.keys).is_err());
+        let settings = parse_settings("[keys.bindings]\nfly = [\"x\"]\n").unwrap();
+        assert!(load_keymap(&settings.keys).is_err());
+    }
+
+    #[test]
+    fn settings_can_be_set_by_key() {
+        let mut settings = Settings::default();
+        settings.set("display.wrap_width", "72").unwrap();
+        settings.set("search.regex", "on").unwrap();
+        settings.set("theme.name", "\"light\"").unwrap();
+        assert_eq!(settings.display.wrap_width, 72);
+        assert!(settings.search.regex);
+        assert_eq!(settings.theme.name, "light");
+        assert_eq!(settings.get("display.wrap_width").as_deref(), Some("72"));
+        assert!(SETTING_KEYS.iter().all(|key| settings.get(key).is_some()));
+        assert!(settings.set("display.bidi", "maybe").is_err());
+        assert!(settings.set("display.image_colors", "16").is_err());
+        assert!(settings.set("display.colour", "red").is_err());
+    }
+
+    #[test]
+    fn missing_settings_use_defaults() {
+        let settings = parse_settings("[display]\nwrap_width = 80\n").unwrap();
+        assert_eq!(settings.display.wrap_width, 80);
+        assert_eq!(settings.downloads.download_dir, "$HOME/Downloads/");
+    }
 }
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use crate::gemtext::TokenKind;
+use crate::highlight::HighlightKind;
+
+pub const BUILTIN_THEMES: [&str; 3] = ["dark", "light", "basic"];
+
+// A color as written in config.toml: one of the eight terminal color names,
+// optionally prefixed with "light ", "default" for the terminal's own color,
+// or a "#rrggbb" hex triplet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ThemeColor {
+    Default,
+    Dark(u8),
+    Light(u8),
+    Rgb(u8, u8, u8),
+}
+
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+impl FromStr for ThemeColor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim().to_lowercase();
+        if s == "default" {
+            return Ok(ThemeColor::Default);
+        }
+        if let Some(hex) = s.strip_prefix('#') {
+            // from_str_radix takes a leading sign, so check the digits first.
+            if hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                if let Ok(value) = u32::from_str_radix(hex, 16) {
+                    return Ok(ThemeColor::Rgb(
+                        (value >> 16) as u8,
+                        (value >> 8) as u8,
+                        value as u8,
+                    ));
+                }
+            }
+            return Err(format!("\"{}\" is not a #rrggbb color", s));
+        }
+        let (light, name) = match s.strip_prefix("light ") {
+            Some(name) => (true, name),
+            None => (false, s.as_str()),
+        };
+        match COLOR_NAMES.iter().position(|n| *n == name) {
+            Some(i) if light => Ok(ThemeColor::Light(i as u8)),
+            Some(i) => Ok(ThemeColor::Dark(i as u8)),
+            None => Err(format!("\"{}\" is not a color", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeColor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeEffect {
+    Bold,
+    Italic,
+    Underline,
+    Reverse,
+    Strikethrough,
+}
+
+// How a single element is drawn. Unset colors are inherited from whatever
+// the element is drawn on.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ElementStyle {
+    pub fg: Option<ThemeColor>,
+    pub bg: Option<ThemeColor>,
+    pub effects: Vec<ThemeEffect>,
+}
+
+impl ElementStyle {
+    fn new(fg: Option<ThemeColor>, bg: Option<ThemeColor>,
+           effects: &[ThemeEffect]) -> ElementStyle {
+        ElementStyle {
+            fg,
+            bg,
+            effects: effects.to_vec(),
+        }
+    }
+}
+
+// Everything a theme can style, gemtext line types first, then the syntax
+// highlighting kinds and finally parts of the interface.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Element {
+    Text,
+    Link,
+    VisitedLink,
+    // Links leaving gemini space, like http, gopher and mailto.
+    ExternalLink,
+    Heading,
+    SubHeading,
+    SubSubHeading,
+    Quote,
+    List,
+    Preformatted,
+    Keyword,
+    String,
+    Comment,
+    Constant,
+    Function,
+    Type,
+    // The terminal background around views.
+    Background,
+    // Text and background of views.
+    View,
+    // Borders and less important text.
+    Secondary,
+    // Dialog titles and the tab bar.
+    Title,
+    // Focused buttons and selections.
+    Highlight,
+    // The labels shown over links in hint mode.
+    Hint,
+    // Matches of an in-page search, and the one last jumped to.
+    SearchMatch,
+    CurrentMatch,
+}
+
+impl Element {
+    pub const ALL: [Element; 24] = [
+        Element::Text,
+        Element::Link,
+        Element::VisitedLink,
+        Element::ExternalLink,
+        Element::Heading,
+        Element::SubHeading,
+        Element::SubSubHeading,
+        Element::Quote,
+        Element::List,
+        Element::Preformatted,
+        Element::Keyword,
+        Element::String,
+        Element::Comment,
+        Element::Constant,
+        Element::Function,
+        Element::Type,
+        Element::Background,
+        Element::View,
+        Element::Secondary,
+        Element::Title,
+        Element::Highlight,
+        Element::Hint,
+        Element::SearchMatch,
+        Element::CurrentMatch,
+    ];
+
+    // The key used for this element in the [theme.styles] config table.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Element::Text => "text",
+            Element::Link => "link",
+            Element::VisitedLink => "visited_link",
+            Element::ExternalLink => "external_link",
+            Element::Heading => "heading",
+            Element::SubHeading => "sub_heading",
+            Element::SubSubHeading => "sub_sub_heading",
+            Element::Quote => "quote",
+            Element::List => "list",
+            Element::Preformatted => "preformatted",
+            Element::Keyword => "keyword",
+            Element::String => "string",
+            Element::Comment => "comment",
+            Element::Constant => "constant",
+            Element::Function => "function",
+            Element::Type => "type",
+            Element::Background => "background",
+            Element::View => "view",
+            Element::Secondary => "secondary",
+            Element::Title => "title",
+            Element::Highlight => "highlight",
+            Element::Hint => "hint",
+            Element::SearchMatch => "search_match",
+            Element::CurrentMatch => "current_match",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Element> {
+        Element::ALL.iter().find(|e| e.name() == name).copied()
+    }
+
+    pub fn for_token(kind: TokenKind) -> Element {
+        match kind {
+            TokenKind::Text => Element::Text,
+            TokenKind::Link => Element::Link,
+            TokenKind::UnorderedList => Element::List,
+            TokenKind::Blockquote => Element::Quote,
+            TokenKind::Heading => Element::Heading,
+            TokenKind::SubHeading => Element::SubHeading,
+            TokenKind::SubSubHeading => Element::SubSubHeading,
+            TokenKind::PreFormattedText => Element::Preformatted,
+        }
+    }
+
+    pub fn for_highlight(kind: HighlightKind) -> Element {
+        match kind {
+            HighlightKind::Keyword => Element::Keyword,
+            HighlightKind::String => Element::String,
+            HighlightKind::Comment => Element::Comment,
+            HighlightKind::Constant => Element::Constant,
+            HighlightKind::Function => Element::Function,
+            HighlightKind::Type => Element::Type,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub name: String,
+    styles: HashMap<Element, ElementStyle>,
+}
+
+impl Theme {
+    // Returns one of the themes in BUILTIN_THEMES.
+    pub fn builtin(name: &str) -> Option<Theme> {
+        use ThemeColor::*;
+        use ThemeEffect::*;
+
+        const BLACK: u8 = 0;
+        const GREEN: u8 = 2;
+        const YELLOW: u8 = 3;
+        const BLUE: u8 = 4;
+        const MAGENTA: u8 = 5;
+        const CYAN: u8 = 6;
+        const WHITE: u8 = 7;
+
+        let (fg, bg, accent, visited, external, muted, highlight) = match name {
+            "dark" => (Light(WHITE), Rgb(0, 0, 0), Rgb(95, 175, 255),
+                       Rgb(175, 135, 255), Rgb(95, 215, 175),
+                       Rgb(138, 138, 138), Rgb(48, 48, 48)),
+            "light" => (Rgb(28, 28, 28), Rgb(250, 250, 250), Rgb(0, 95, 175),
+                        Rgb(135, 0, 175), Rgb(0, 135, 95),
+                        Rgb(108, 108, 108), Rgb(215, 215, 215)),
+            // Only the 16 standard colors, for terminals that can't do more.
+            "basic" => (Default, Default, Dark(BLUE), Dark(MAGENTA),
+                        Dark(CYAN), Light(BLACK), Dark(BLUE)),
+            _ => return None,
+        };
+
+        let s = ElementStyle::new;
+        let styles = vec![
+            (Element::Text, s(None, None, &[])),
+            (Element::Link, s(Some(accent), None, &[Underline])),
+            (Element::VisitedLink, s(Some(visited), None, &[Underline])),
+            (Element::ExternalLink, s(Some(external), None, &[Underline])),
+            (Element::Heading, s(None, None, &[Bold, Underline])),
+            (Element::SubHeading, s(None, None, &[Bold])),
+            (Element::SubSubHeading, s(Some(muted), None, &[Bold])),
+            (Element::Quote, s(Some(muted), None, &[Italic])),
+            (Element::List, s(None, None, &[])),
+            (Element::Preformatted, s(None, None, &[])),
+            (Element::Keyword, s(Some(Dark(MAGENTA)), None, &[Bold])),
+            (Element::String, s(Some(Dark(GREEN)), None, &[])),
+            (Element::Comment, s(Some(muted), None, &[Italic])),
+            (Element::Constant, s(Some(Dark(YELLOW)), None, &[])),
+            (Element::Function, s(Some(Dark(BLUE)), None, &[])),
+            (Element::Type, s(Some(Dark(CYAN)), None, &[])),
+            (Element::Background, s(None, Some(bg), &[])),
+            (Element::View, s(Some(fg), Some(bg), &[])),
+            (Element::Secondary, s(Some(muted), None, &[])),
+            (Element::Title, s(Some(fg), None, &[Bold])),
+            (Element::Highlight, s(Some(fg), Some(highlight), &[])),
+            (Element::Hint, s(Some(bg), Some(accent), &[Bold])),
+            (Element::SearchMatch, s(Some(Dark(BLACK)), Some(Dark(YELLOW)), &[])),
+            (Element::CurrentMatch, s(Some(Dark(BLACK)), Some(Light(YELLOW)), &[Bold])),
+        ];
+        Some(Theme {
+            name: name.to_owned(),
+            styles: styles.into_iter().collect(),
+        })
+    }
+
+    pub fn style(&self, element: Element) -> ElementStyle {
+        self.styles.get(&element).cloned().unwrap_or_default()
+    }
+
+    pub fn set_style(&mut self, element: Element, style: ElementStyle) {
+        self.styles.insert(element, style);
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::builtin("dark").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colors_parse() {
+        assert_eq!("red".parse(), Ok(ThemeColor::Dark(1)));
+        assert_eq!("Light Blue".parse(), Ok(ThemeColor::Light(4)));
+        assert_eq!("#ff8000".parse(), Ok(ThemeColor::Rgb(255, 128, 0)));
+        assert_eq!("default".parse(), Ok(ThemeColor::Default));
+        assert!("#ff80".parse::<ThemeColor>().is_err());
+        assert!("#+fffff".parse::<ThemeColor>().is_err());
+        assert!("purple".parse::<ThemeColor>().is_err());
+    }
+
+    #[test]
+    fn builtin_themes_style_every_element() {
+        for name in BUILTIN_THEMES.iter() {
+            let theme = Theme::builtin(name).unwrap();
+            for element in Element::ALL.iter() {
+                assert!(theme.styles.contains_key(element),
+                    "{} doesn't style {}", name, element.name());
+            }
+        }
+    }
+
+    #[test]
+    fn basic_theme_only_uses_16_colors() {
+        let theme = Theme::builtin("basic").unwrap();
+        for element in Element::ALL.iter() {
+            let style = theme.style(*element);
+            for color in style.fg.iter().chain(style.bg.iter()) {
+                assert!(!matches!(color, ThemeColor::Rgb(_, _, _)));
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::BufReader;
+
+use rustls::{Certificate, PrivateKey};
+use rustls_pemfile::Item;
+use url::Url;
+
+use crate::settings::{expand_home, IdentitySettings};
+
+// A client certificate loaded and ready to be sent.
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub name: String,
+    pub certificates: Vec<Certificate>,
+    pub key: PrivateKey,
+}
+
+// Handles identities whose certificate or key can't be read.
+#[derive(Clone, Debug)]
+pub struct IdentityError {
+    details: String,
+}
+
+impl std::fmt::Display for IdentityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Couldn't load identity: {}", self.details)
+    }
+}
+
+impl IdentityError {
+    fn new(message: &str) -> IdentityError {
+        IdentityError {
+            details: message.to_owned(),
+        }
+    }
+}
+
+// Picks the identity for url, the one with the longest matching prefix when
+// several match.
+pub fn identity_for<'a>(identities: &'a [IdentitySettings], url: &Url)
+    -> Option<&'a IdentitySettings> {
+    identities
+        .iter()
+        .filter_map(|identity| {
+            identity.urls
+                .iter()
+                .filter(|prefix| url.as_str().starts_with(prefix.as_str()))
+                .map(|prefix| prefix.len())
+                .max()
+                .map(|len| (len, identity))
+        })
+        .max_by_key(|(len, _)| *len)
+        .map(|(_, identity)| identity)
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, IdentityError> {
+    let path = expand_home(path);
+    let file = File::open(&path)
+        .map_err(|e| IdentityError::new(&format!("{}: {}", path.display(), e)))?;
+    rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| IdentityError::new(&format!("{}: {}", path.display(), e)))
+}
+
+pub fn load_identity(settings: &IdentitySettings) -> Result<Identity, IdentityError> {
+    let certificates: Vec<Certificate> = read_pem(&settings.cert)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        return Err(IdentityError::new(&format!("no certificate in {}", settings.cert)));
+    }
+    let key = read_pem(&settings.key)?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::RSAKey(der) | Item::PKCS8Key(der) | Item::ECKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        });
+    match key {
+        Some(key) => Ok(Identity {
+            name: settings.name.clone(),
+            certificates,
+            key,
+        }),
+        None => Err(IdentityError::new(&format!("no private key in {}", settings.key))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_prefix_picks_the_identity() {
+        let identity = |name: &str, urls: &[&str]| IdentitySettings {
+            name: name.to_owned(),
+            urls: urls.iter().map(|url| url.to_string()).collect(),
+            ..Default::default()
+        };
+        let identities = vec![
+            identity("site", &["gemini://example.org/"]),
+            identity("app", &["gemini://other.org/", "gemini://example.org/app/"]),
+        ];
+        let pick = |url: &str| {
+            identity_for(&identities, &Url::parse(url).unwrap()).map(|i| i.name.as_str())
+        };
+        assert_eq!(pick("gemini://example.org/app/login"), Some("app"));
+        assert_eq!(pick("gemini://example.org/blog/"), Some("site"));
+        assert_eq!(pick("gemini://elsewhere.org/"), None);
+    }
+}
@@ -2,13 +2,23 @@
 //    - status,
 //    - mimetype (default: text/gemini).
 //    - charset (default: charset=utf-8),
-//    - body.
+//    - lang (default: empty, meaning unknown),
+//    - meta, the header line after the status, as sent,
+//    - body, the text of a text/* response or a message about the status,
+//    - data, the raw body of a successful response.
+// The charsets text bodies can be decoded from. US-ASCII is a subset of
+// UTF-8, so it's decoded as UTF-8.
+const SUPPORTED_CHARSETS: [&str; 6] = ["utf-8", "utf8", "us-ascii", "ascii", "iso-8859-1", "latin1"];
+
 #[derive(Debug)]
 pub struct Response {
     pub status: u8,
+    pub meta: String,
     pub mimetype: String,
     pub charset: String,
+    pub lang: String,
     pub body: String,
+    pub data: Vec<u8>,
 }
 
 impl Response {
@@ -33,8 +43,8 @@ impl Response {
             Err(_e) => return Err(ResponseError::new(ResponseErrorKind::MissingStatus,
                     "<STATUS> is missing, header may be malformed"))
         };
+        let sent_meta = header_tokens.get(1).map(|meta| meta.trim()).unwrap_or("");
         let meta: &str;
-        let charset: &str;
 
         match status {
             // TODO: Handle 1x statuses.
@@ -46,41 +56,93 @@ impl Response {
                     meta = header_tokens[1];
                 }
                 
-                // Split meta into MIME and charset and set defaults properly.
-                let meta_tokens: Vec<&str> = meta.split(";").collect();
-                let mime = meta_tokens[0];
-                if meta_tokens.len() < 2 && mime[..5].eq("text/") {
-                    charset = "utf-8";
-                } else {
-                    charset = &meta_tokens[1][8..];
+                // Split meta into MIME and its parameters and set defaults
+                // properly.
+                let meta_tokens: Vec<&str> = meta.split(';').collect();
+                // An empty MIME type means gemtext, as when META is missing.
+                let mime = match meta_tokens[0].trim() {
+                    "" => "text/gemini",
+                    mime => mime,
+                };
+                let mut charset = String::new();
+                let mut lang = String::new();
+                for param in &meta_tokens[1..] {
+                    match param.trim().split_once('=') {
+                        Some((key, value)) if key.eq_ignore_ascii_case("charset") => {
+                            charset = value.to_lowercase();
+                        },
+                        Some((key, value)) if key.eq_ignore_ascii_case("lang") => {
+                            lang = value.to_owned();
+                        },
+                        _ => (),
+                    }
+                }
+                if charset.is_empty() && mime.starts_with("text/") {
+                    charset = "utf-8".to_owned();
                 }
                 Ok(Response {
-                    status: status,
+                    status,
+                    meta: sent_meta.to_owned(),
                     mimetype: mime.to_owned(),
-                    charset: charset.to_owned(),
+                    charset,
+                    lang,
                     body: data_tokens[1].to_owned(),
+                    data: data_tokens[1].as_bytes().to_vec(),
                 })
             }
             _ => { 
                 Ok(Response {
-                    status: 20,
+                    status,
+                    meta: sent_meta.to_owned(),
                     mimetype: "text/gemini".to_owned(),
                     charset: "utf-8".to_owned(),
+                    lang: "".to_owned(),
                     body: format!("Status {} is currently unhandled", status),
+                    data: Vec::new(),
                 })
             }
         }
     }
+
+    // Whether the response is one the browser can show as text: a text type
+    // in a charset it can decode.
+    pub fn is_text(&self) -> bool {
+        self.mimetype.starts_with("text/") && self.charset_is_supported()
+    }
+
+    // Whether the body can be decoded. Text in any other charset is treated
+    // like a type that can't be shown, rather than being shown mangled.
+    pub fn charset_is_supported(&self) -> bool {
+        SUPPORTED_CHARSETS.contains(&self.charset.as_str())
+    }
+
+    // Sets the body of a successful response from the bytes that came after
+    // the header. Only text is decoded, anything else is kept as data.
+    pub fn set_body(&mut self, data: Vec<u8>) {
+        if !(20..=29).contains(&self.status) {
+            return;
+        }
+        self.body = match self.charset.as_str() {
+            _ if !self.is_text() => String::new(),
+            // Every byte is the code point of the same number.
+            "iso-8859-1" | "latin1" => data.iter().map(|&byte| char::from(byte)).collect(),
+            _ => String::from_utf8_lossy(&data).into_owned(),
+        };
+        self.data = data;
+    }
 }
 
 pub fn create_fake_response(status: u8, message: &str) -> Response {
     // TODO: Style this screen a bit more.
     let body = format!("Status\n {}\n\n{}", status, message);
     Response {
-        status: status,
+        status,
+        meta: message.to_owned(),
         mimetype: "text/gemini".to_owned(),
         charset: "utf-8".to_owned(),
-        body: body,
+        lang: "".to_owned(),
+        body,
+        data: Vec::new(),
     }
 }
 
@@ -99,7 +161,7 @@ pub struct ResponseError {
 
 impl std::fmt::Display for ResponseError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Malformed response!")
+        write!(f, "Malformed response! {}", self.details)
     }
 }
 
@@ -107,9 +169,13 @@ impl ResponseError {
     fn new(kind: ResponseErrorKind, message: &str) -> ResponseError {
         ResponseError {
             details: message.to_owned(),
-            kind: kind,
+            kind,
         }
     }
+
+    pub fn kind(&self) -> &ResponseErrorKind {
+        &self.kind
+    }
 }
 
 #[cfg(test)]
@@ -136,6 +202,53 @@ mod tests {
         assert_eq!(r.body, "Body");
     }
 
+    #[test]
+    fn lang_parameter_is_parsed() {
+        let data = "20 text/gemini; lang=he\r\nBody";
+        let r = Response::new(data).unwrap();
+        assert_eq!(r.mimetype, "text/gemini");
+        assert_eq!(r.charset, "utf-8");
+        assert_eq!(r.lang, "he");
+    }
+
+    #[test]
+    fn only_text_bodies_are_decoded() {
+        let mut r = Response::new("20 image/png\r\n").unwrap();
+        r.set_body(vec![0x89, b'P', b'N', b'G']);
+        assert_eq!(r.body, "");
+        assert_eq!(r.data.len(), 4);
+        let mut r = Response::new("51 Not found\r\n").unwrap();
+        r.set_body(b"ignored".to_vec());
+        assert_eq!(r.body, "Status 51 is currently unhandled");
+    }
+
+    #[test]
+    fn bodies_are_decoded_from_their_charset() {
+        let mut r = Response::new("20 text/plain; charset=ISO-8859-1\r\n").unwrap();
+        r.set_body(vec![b'c', b'a', b'f', 0xe9]);
+        assert_eq!(r.body, "caf\u{e9}");
+        let mut r = Response::new("20 text/plain; charset=shift_jis\r\n").unwrap();
+        assert!(!r.is_text());
+        r.set_body(vec![0x82, 0xa0]);
+        assert_eq!(r.body, "");
+        assert_eq!(r.data, [0x82, 0xa0]);
+    }
+
+    #[test]
+    fn unhandled_status_keeps_status_and_meta() {
+        let r = Response::new("51 Not found\r\n").unwrap();
+        assert_eq!(r.status, 51);
+        assert_eq!(r.meta, "Not found");
+        assert_eq!(r.mimetype, "text/gemini");
+    }
+
+    #[test]
+    fn empty_meta_means_gemtext() {
+        let r = Response::new("20 \r\n").unwrap();
+        assert_eq!(r.mimetype, "text/gemini");
+        assert_eq!(r.charset, "utf-8");
+    }
+
     #[test]
     fn nonexistent_meta_response_builds() {
         let data = "\r\nBody";
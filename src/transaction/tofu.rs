@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use ring::digest::{digest, SHA256};
+use rustls::{Certificate, Error, ServerName};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use url::Url;
+
+// How a server's certificate compares with the one seen the first time we
+// connected to it. Gemini servers mostly use self-signed certificates, so
+// trust comes from the certificate staying the same.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TrustState {
+    // Never connected to this server before.
+    New,
+    Known,
+    // The server sent a different certificate than last time.
+    Changed,
+}
+
+impl fmt::Display for TrustState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TrustState::New => "new",
+            TrustState::Known => "known",
+            TrustState::Changed => "changed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// The SHA-256 fingerprint of a DER encoded certificate, as hex.
+pub fn fingerprint(certificate: &[u8]) -> String {
+    digest(&SHA256, certificate)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// The "host:port" that known hosts are kept under for url.
+pub fn host_key(url: &Url) -> String {
+    format!("{}:{}", url.host_str().unwrap_or(""), url.port().unwrap_or(1965))
+}
+
+// Checks the server's certificate against the fingerprint known for it while
+// connecting, so a changed certificate ends the handshake before an identity
+// or the request is sent. Anything else is accepted as it is, since gemini
+// servers mostly sign their own certificates.
+pub struct TofuVerifier {
+    known: Option<String>,
+    // The fingerprint of a certificate refused for not being the known one.
+    changed: Mutex<Option<String>>,
+}
+
+impl TofuVerifier {
+    pub fn new(known: Option<&str>) -> Self {
+        TofuVerifier {
+            known: known.map(|known| known.to_owned()),
+            changed: Mutex::new(None),
+        }
+    }
+
+    // The fingerprint of the certificate that was refused, if one was.
+    pub fn changed(&self) -> Option<String> {
+        self.changed.lock().ok().and_then(|changed| changed.clone())
+    }
+}
+
+impl ServerCertVerifier for TofuVerifier {
+    fn verify_server_cert(
+        &self,
+        certificate: &Certificate,
+        _: &[Certificate],
+        _: &ServerName,
+        _: &mut dyn Iterator<Item = &[u8]>,
+        _: &[u8],
+        _: SystemTime
+    ) -> Result<ServerCertVerified, Error> {
+        let seen = fingerprint(&certificate.0);
+        match &self.known {
+            Some(known) if *known != seen => {
+                if let Ok(mut changed) = self.changed.lock() {
+                    *changed = Some(seen);
+                }
+                Err(Error::InvalidCertificateData(
+                        "the certificate has changed since it was first seen".to_owned()))
+            },
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+}
+
+// The certificate fingerprint first seen for every server, keyed by
+// "host:port". When backed by a file each server is a "<host:port> <sha256>"
+// line.
+#[derive(Clone, Debug, Default)]
+pub struct KnownHosts {
+    hosts: HashMap<String, String>,
+    path: Option<PathBuf>,
+}
+
+impl KnownHosts {
+    // Known hosts that are never written to disk.
+    pub fn in_memory() -> KnownHosts {
+        KnownHosts::default()
+    }
+
+    // Reads the known hosts file at path. A missing or unreadable file gives
+    // no known hosts, which will still be saved to path.
+    pub fn load(path: &Path) -> KnownHosts {
+        let contents = fs::read_to_string(path).unwrap_or_default();
+        let hosts = contents
+            .lines()
+            .filter_map(|line| line.split_once(' '))
+            .map(|(host, fingerprint)| (host.to_owned(), fingerprint.trim().to_owned()))
+            .collect();
+        KnownHosts {
+            hosts,
+            path: Some(path.to_path_buf()),
+        }
+    }
+
+    // Compares fingerprint with the one remembered for host, remembering it
+    // if host is new. A changed certificate is not remembered, so it's
+    // reported every time until the user trusts it.
+    pub fn check(&mut self, host: &str, fingerprint: &str) -> TrustState {
+        match self.hosts.get(host) {
+            Some(known) if known == fingerprint => TrustState::Known,
+            Some(_) => TrustState::Changed,
+            None => {
+                self.trust(host, fingerprint);
+                TrustState::New
+            },
+        }
+    }
+
+    // Remembers fingerprint for host, replacing whatever was there before.
+    pub fn trust(&mut self, host: &str, fingerprint: &str) {
+        self.hosts.insert(host.to_owned(), fingerprint.to_owned());
+        self.save();
+    }
+
+    // The fingerprint remembered for host, if it's been seen before.
+    pub fn get(&self, host: &str) -> Option<&str> {
+        self.hosts.get(host).map(|known| known.as_str())
+    }
+
+    pub fn hosts(&self) -> &HashMap<String, String> {
+        &self.hosts
+    }
+
+    fn save(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut hosts: Vec<_> = self.hosts.iter().collect();
+        hosts.sort();
+        let contents: String = hosts
+            .iter()
+            .map(|(host, fingerprint)| format!("{} {}\n", host, fingerprint))
+            .collect();
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(path, contents);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificates_are_trusted_on_first_use() {
+        let path = Path::new("/tmp/armstrong_known_hosts_test");
+        let _ = fs::remove_file(path);
+        let mut known_hosts = KnownHosts::load(path);
+        let first = fingerprint(b"first certificate");
+        assert_eq!(first.len(), 64);
+        assert_eq!(known_hosts.check("example.org:1965", &first), TrustState::New);
+        assert_eq!(known_hosts.check("example.org:1965", &first), TrustState::Known);
+
+        let mut reloaded = KnownHosts::load(path);
+        let second = fingerprint(b"second certificate");
+        assert_eq!(reloaded.check("example.org:1965", &first), TrustState::Known);
+        assert_eq!(reloaded.check("example.org:1965", &second), TrustState::Changed);
+        assert_eq!(reloaded.check("example.org:1965", &second), TrustState::Changed);
+
+        reloaded.trust("example.org:1965", &second);
+        assert_eq!(KnownHosts::load(path).get("example.org:1965"), Some(second.as_str()));
+        let _ = fs::remove_file(path);
+    }
+}
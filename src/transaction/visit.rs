@@ -1,24 +1,155 @@
-use std::io::{Read, Write};
+use std::io::{self, Cursor, Read, Write};
 use std::net::TcpStream;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use rustls::{ClientConnection, StreamOwned};
 use url::Url;
 
+use crate::about::{about_response, AboutSources};
 use crate::transaction::response::{
-    create_fake_response, 
-    Response, 
+    create_fake_response,
+    Response,
 };
-use crate::transaction::dummy_verifier::DummyVerifier;
+use crate::transaction::identity::Identity;
+use crate::transaction::tofu::{fingerprint, host_key, KnownHosts, TofuVerifier};
+
+// Longest header a server may send: a two digit status, a space, 1024 bytes
+// of META and CRLF.
+const MAX_HEADER_LEN: usize = 1029;
+
+// A Response along with what we learned about the connection it came over.
+#[derive(Debug)]
+pub struct Transaction {
+    pub response: Response,
+    // Fingerprint of the server's certificate, if the handshake got that far.
+    pub fingerprint: Option<String>,
+    pub elapsed: Duration,
+}
+
+// Handles requests that couldn't be made. When the server's certificate
+// wasn't the one known for it, changed holds the fingerprint it sent.
+#[derive(Clone, Debug)]
+pub struct OpenError {
+    details: String,
+    pub changed: Option<String>,
+}
+
+impl std::fmt::Display for OpenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.details)
+    }
+}
+
+impl OpenError {
+    pub fn new(message: &str) -> OpenError {
+        OpenError {
+            details: message.to_owned(),
+            changed: None,
+        }
+    }
+}
+
+// A response whose header has been read but whose body is still on its way.
+// Reading from it reads the body.
+pub struct ResponseStream {
+    // The response so far, without a body.
+    pub response: Response,
+    pub fingerprint: Option<String>,
+    started: Instant,
+    // The connection, or the body itself for a page from about:.
+    stream: Box<dyn Read + Send>,
+}
+
+impl Read for ResponseStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream.read(buf) {
+            // Plenty of servers hang up without a TLS close_notify, which
+            // still marks the end of the body in gemini.
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+            result => result,
+        }
+    }
+}
+
+impl ResponseStream {
+    // A response that's already all here, like an about: page.
+    fn local(mut response: Response) -> ResponseStream {
+        let body = Cursor::new(response.data.clone());
+        response.set_body(Vec::new());
+        ResponseStream {
+            response,
+            fingerprint: None,
+            started: Instant::now(),
+            stream: Box::new(body),
+        }
+    }
+
+    // Reads the rest of the body and completes the transaction.
+    pub fn finish(mut self) -> Transaction {
+        let mut data = Vec::new();
+        let _ = self.read_to_end(&mut data);
+        self.response.set_body(data);
+        Transaction {
+            response: self.response,
+            fingerprint: self.fingerprint,
+            elapsed: self.started.elapsed(),
+        }
+    }
+}
 
 // Visits the specified url at the given port and returns the resulting
 // Response.
 pub fn visit(url: &Url) -> Response {
-    let for_tcp = format!("{}:{}", url.host_str().unwrap(), 1965);
-    let request = format!("{}://{}:{}/{}\r\n",
-        "gemini",
-        url.host_str().unwrap(),
-        1965,
-        url.path());
+    request(url, None).response
+}
+
+// Requests url, sending identity's certificate if there is one.
+pub fn request(url: &Url, identity: Option<&Identity>) -> Transaction {
+    let started = Instant::now();
+    match open_gemini(url, identity, None) {
+        Ok(stream) => stream.finish(),
+        Err(error) => Transaction {
+            response: create_fake_response(20, &error.to_string()),
+            fingerprint: None,
+            elapsed: started.elapsed(),
+        },
+    }
+}
+
+// Opens url wherever it is: on a capsule, sending identity and checking the
+// server's certificate against known_hosts, or one of the browser's own
+// about: pages, built from about.
+pub fn open(url: &Url, identity: Option<&Identity>, known_hosts: &KnownHosts,
+            about: &AboutSources) -> Result<ResponseStream, OpenError> {
+    match url.scheme() {
+        "gemini" => open_gemini(url, identity, known_hosts.get(&host_key(url))),
+        "about" => Ok(ResponseStream::local(about_response(url, about))),
+        scheme => Err(OpenError::new(&format!("Can't open {} links", scheme))),
+    }
+}
+
+// Sends the request for url and reads the response header, leaving the body
+// to be read from the returned stream. known is the fingerprint the server's
+// certificate should have, if it's been seen before; a different one stops
+// the request before anything is sent.
+pub fn open_gemini(url: &Url, identity: Option<&Identity>, known: Option<&str>)
+    -> Result<ResponseStream, OpenError> {
+    let verifier = Arc::new(TofuVerifier::new(known));
+    connect(url, identity, verifier.clone()).map_err(|details| OpenError {
+        details,
+        changed: verifier.changed(),
+    })
+}
+
+fn connect(url: &Url, identity: Option<&Identity>, verifier: Arc<TofuVerifier>)
+    -> Result<ResponseStream, String> {
+    let started = Instant::now();
+    let for_tcp = format!("{}:{}", url.host_str().unwrap_or(""), url.port().unwrap_or(1965));
+    // Requests are the absolute URL, which never includes the fragment.
+    let mut target = url.clone();
+    target.set_fragment(None);
+    let request = format!("{}\r\n", target);
 
     // TLS stuff.
     let mut root_store = rustls::RootCertStore::empty();
@@ -34,62 +165,79 @@ pub fn visit(url: &Url) -> Response {
             )
         })
     );
-    let mut cfg = rustls::ClientConfig::builder()
+    let builder = rustls::ClientConfig::builder()
         .with_safe_defaults()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
+        .with_root_certificates(root_store);
+    let mut cfg = match identity {
+        Some(identity) => builder
+            .with_single_cert(identity.certificates.clone(), identity.key.clone())
+            .map_err(|error| error.to_string())?,
+        None => builder.with_no_client_auth(),
+    };
     let mut config = rustls::client::DangerousClientConfig {
         cfg: &mut cfg,
     };
-    let dummy_verifier = Arc::new(DummyVerifier::new());
-    config.set_certificate_verifier(dummy_verifier);
+    config.set_certificate_verifier(verifier);
     let rc_config = Arc::new(cfg);
-    let hostname: rustls::ServerName = url.host_str().unwrap().try_into().unwrap();
-    let mut client = match rustls::ClientConnection::new(rc_config, hostname) {
-        Ok(client) => client,
-        Err(error) => {
-            return create_fake_response(20, &error.to_string());
-        }
-    };
+    let host = url.host_str().unwrap_or("");
+    let hostname: rustls::ServerName = host
+        .try_into()
+        .map_err(|_| format!("Can't connect to \"{}\"", host))?;
+    let client = ClientConnection::new(rc_config, hostname).map_err(|e| e.to_string())?;
 
     // Open gemini connection
-    let mut socket = match TcpStream::connect(for_tcp) {
-        Ok(socket) => socket,
-        Err(error) => {
-            return create_fake_response(20, &error.to_string());
-        }
-    };
-
-    let mut stream = rustls::Stream::new(&mut client, &mut socket);
+    let socket = TcpStream::connect(for_tcp).map_err(|e| e.to_string())?;
+    let mut stream = StreamOwned::new(client, socket);
+    stream.write_all(request.as_bytes()).map_err(|e| e.to_string())?;
+    let fingerprint = stream.conn
+        .peer_certificates()
+        .and_then(|certificates| certificates.first())
+        .map(|certificate| fingerprint(&certificate.0));
 
-    // Get data
-    let mut data = Vec::new();
-    stream.write(request.as_bytes()).unwrap();
-    while client.wants_read() {
-        client.read_tls(&mut socket).unwrap();
-        client.process_new_packets().unwrap();
-    }
-    let _ = client.reader().read_to_end(&mut data);
-    let content = String::from_utf8_lossy(&data).to_string();
-
-    let response = match Response::new(&content) {
-        Ok(response) => response,
-        Err(error) => {
-            return create_fake_response(20, &error.to_string());
-        }
+    let mut stream = ResponseStream {
+        response: create_fake_response(20, ""),
+        fingerprint,
+        started,
+        stream: Box::new(stream),
     };
-    response
+    // The header is read a byte at a time so none of the body is read with
+    // it.
+    let mut header = Vec::new();
+    let mut byte = [0];
+    while header.len() < MAX_HEADER_LEN && !header.ends_with(b"\r\n") {
+        match stream.read(&mut byte) {
+            Ok(0) => break,
+            Ok(_) => header.push(byte[0]),
+            Err(error) => return Err(error.to_string()),
+        }
+    }
+    let mut response = Response::new(&String::from_utf8_lossy(&header))
+        .map_err(|error| error.to_string())?;
+    response.set_body(Vec::new());
+    stream.response = response;
+    Ok(stream)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Reads a response the way one from a server is read, but from memory,
+    // so the test doesn't need the network.
     #[test]
-    fn visit_to_valid_site_returns_ok_status() {
-        let response: Response = visit("gemini://carcosa.net:1965");
-        assert_eq!(response.status, 20);
-        assert_eq!(response.mimetype, "text/gemini");
-        assert_eq!(response.charset, "utf-8");
+    fn response_stream_reads_header_and_body() {
+        let response = Response::new("20 text/gemini; charset=utf-8\r\n# Hi\n").unwrap();
+        let mut stream = ResponseStream::local(response);
+        assert_eq!(stream.response.status, 20);
+        assert!(stream.response.body.is_empty());
+        let mut first = [0; 2];
+        stream.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"# ");
+
+        let transaction = stream.finish();
+        assert_eq!(transaction.response.mimetype, "text/gemini");
+        assert_eq!(transaction.response.charset, "utf-8");
+        assert_eq!(transaction.response.body, "Hi\n");
+        assert_eq!(transaction.fingerprint, None);
     }
 }
@@ -0,0 +1,148 @@
+use std::cell::Cell;
+
+use cursive::{Cursive, Printer, Rect};
+use cursive::event::{Event, EventResult, EventTrigger, Key, MouseEvent};
+use cursive::view::{Finder, Nameable, Resizable, View, ViewWrapper};
+use cursive::views::{EditView, LinearLayout, OnEventView, TextView};
+
+use crate::address::{complete_address, parse_address};
+use crate::ui::browser::Browser;
+use crate::ui::completions::Completions;
+use crate::ui::tui::{open_url, swallow_keys};
+
+// The completions offered for what was last typed into the address bar.
+#[derive(Clone, Debug, Default)]
+pub struct AddressBarState {
+    completions: Completions,
+}
+
+// The line above the page showing the current URL. It only takes focus
+// while being edited, so the page keeps its keys the rest of the time, and
+// clicks are handed to it by the view around the page.
+pub struct AddressBar {
+    layout: LinearLayout,
+    // Where the bar was last drawn on the screen.
+    area: Cell<Option<Rect>>,
+}
+
+impl AddressBar {
+    // Starts editing the address when it's clicked. The first click
+    // anywhere else while editing gives up on the edit.
+    pub fn on_mouse(&mut self, event: &Event) -> Option<EventResult> {
+        let position = match *event {
+            Event::Mouse { position, event: MouseEvent::Press(_), .. } => position,
+            _ => return None,
+        };
+        if self.area.get().is_some_and(|area| area.contains(position)) {
+            return Some(EventResult::with_cb(edit_address));
+        }
+        let editing = self.layout.call_on_name("address_box", |view: &mut EditView| view.is_enabled());
+        match editing {
+            Some(true) => Some(EventResult::with_cb(close_address_bar)),
+            _ => None,
+        }
+    }
+}
+
+impl ViewWrapper for AddressBar {
+    cursive::wrap_impl!(self.layout: LinearLayout);
+
+    fn wrap_draw(&self, printer: &Printer) {
+        self.area.set(Some(Rect::from_size(printer.offset, printer.size)));
+        self.layout.draw(printer);
+    }
+}
+
+pub fn address_bar() -> AddressBar {
+    let address_box = OnEventView::new(
+        EditView::new()
+            .on_submit(submit)
+            .disabled()
+            .with_name("address_box"))
+        .on_event(Key::Esc, close_address_bar)
+        .on_event(Key::Tab, complete)
+        // Clears the line, as in a shell.
+        .on_event(Event::CtrlChar('u'), |s| set_address(s, ""))
+        .on_event_inner(EventTrigger::any(), |_, event| swallow_keys(event));
+
+    AddressBar {
+        layout: LinearLayout::horizontal()
+            .child(address_box.full_width())
+            .child(TextView::new("").with_name("address_status")),
+        area: Cell::new(None),
+    }
+}
+
+// Puts the address bar into editing mode with the current URL in it.
+pub fn edit_address(app: &mut Cursive) {
+    if let Some(browser) = app.user_data::<Browser>() {
+        browser.address_bar.completions.clear();
+    }
+    show_address(app);
+    app.call_on_name("address_box", |view: &mut EditView| view.enable());
+    let _ = app.focus_name("address_box");
+}
+
+// Shows the current tab's URL, dropping anything that was being typed.
+pub fn show_address(app: &mut Cursive) {
+    let url = app.user_data::<Browser>()
+        .and_then(|browser| browser.tab().url.as_ref().map(|url| url.to_string()))
+        .unwrap_or_default();
+    set_address(app, &url);
+    set_status(app, "");
+}
+
+pub fn close_address_bar(app: &mut Cursive) {
+    show_address(app);
+    app.call_on_name("address_box", |view: &mut EditView| view.disable());
+    let _ = app.focus_name("page");
+}
+
+fn set_address(app: &mut Cursive, address: &str) {
+    app.call_on_name("address_box", |view: &mut EditView| {
+        view.set_content(address);
+    });
+}
+
+fn set_status(app: &mut Cursive, status: &str) {
+    let status = status.to_owned();
+    app.call_on_name("address_status", |view: &mut TextView| view.set_content(status));
+}
+
+// Opens what was typed, or says what's wrong with it and leaves it to be
+// fixed.
+fn submit(app: &mut Cursive, address: &str) {
+    let search_url = match app.user_data::<Browser>() {
+        Some(browser) => browser.settings.general.search_url.clone(),
+        None => return,
+    };
+    match parse_address(address, &search_url) {
+        Ok(url) => {
+            close_address_bar(app);
+            open_url(app, url);
+        },
+        Err(error) => set_status(app, &format!(" {} ", error)),
+    }
+}
+
+// Completes the address from bookmarks and history, or moves on to the next
+// completion when the address is still the one completed last time.
+fn complete(app: &mut Cursive) {
+    let address = app
+        .call_on_name("address_box", |view: &mut EditView| view.get_content())
+        .map(|address| address.to_string())
+        .unwrap_or_default();
+    let (completed, status) = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let urls = browser.known_urls();
+            let completions = &mut browser.address_bar.completions;
+            let completed = completions
+                .next(&address, || complete_address(&address, &urls))
+                .map(str::to_owned);
+            (completed.unwrap_or(address), format!(" {} ", completions.status()))
+        },
+        None => return,
+    };
+    set_address(app, &completed);
+    set_status(app, &status);
+}
@@ -1,8 +1,400 @@
-use cursive::utils::markup::StyledString;
+use std::rc::Rc;
+use std::time::Duration;
+
+use image::RgbaImage;
+
 use url::Url;
 
+use crate::about::AboutSources;
+use crate::bookmarks::Bookmarks;
+use crate::downloads::{file_name, format_size, Downloads};
+use crate::gemtext::{GemtextToken, TokenKind};
+use crate::history::History;
+use crate::keymap::{KeyPress, Keymap};
+use crate::layout::Direction;
+use crate::session::{Session, TabSession};
+use crate::settings::Settings;
+use crate::transaction::identity::{identity_for, load_identity, Identity, IdentityError};
+use crate::transaction::tofu::{KnownHosts, TrustState};
+use crate::ui::address_bar::AddressBarState;
+use crate::ui::command_line::CommandLineState;
+
+// What the server sent back for a page and how it got here, for the status
+// bar.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageInfo {
+    pub status: u8,
+    pub meta: String,
+    pub mimetype: String,
+    pub charset: String,
+    // Size of the body in bytes.
+    pub size: usize,
+    pub elapsed: Duration,
+    // None when the connection failed before a certificate was seen.
+    pub trust: Option<TrustState>,
+    // The name of the client certificate that was sent, if any.
+    pub identity: Option<String>,
+}
+
+impl PageInfo {
+    // Sums the page up as "20 text/gemini · utf-8 · 1.2 KB · 85 ms · cert
+    // known · no identity". Successful responses have the mimetype as META,
+    // so only the charset is added, and only if META didn't give it.
+    pub fn summary(&self) -> String {
+        let success = (20..=29).contains(&self.status);
+        let meta = if success && self.meta.is_empty() { &self.mimetype } else { &self.meta };
+        let mut parts = vec![format!("{} {}", self.status, meta).trim_end().to_owned()];
+        if success && !self.charset.is_empty() && !meta.to_lowercase().contains("charset") {
+            parts.push(self.charset.clone());
+        }
+        parts.push(format_size(self.size));
+        parts.push(format!("{} ms", self.elapsed.as_millis()));
+        if let Some(trust) = self.trust {
+            parts.push(format!("cert {}", trust));
+        }
+        parts.push(match &self.identity {
+            Some(name) => format!("identity {}", name),
+            None => "no identity".to_owned(),
+        });
+        parts.join(" · ")
+    }
+}
+
+// A response as it came over the wire, for viewing the page source.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Source {
+    // The status and META.
+    pub header: String,
+    pub body: Vec<u8>,
+}
+
+impl Source {
+    // The header followed by the numbered lines of the body. A body that
+    // isn't UTF-8 is shown as a hex dump, numbered by offset instead.
+    pub fn text(&self) -> String {
+        let mut text = format!("{}\n\n", self.header);
+        match std::str::from_utf8(&self.body) {
+            Ok(body) => {
+                let count = body.lines().count();
+                let width = count.to_string().len();
+                for (i, line) in body.lines().enumerate() {
+                    text.push_str(&format!("{:>width$}  {}\n", i + 1, line, width = width));
+                }
+            },
+            Err(_) => {
+                for (i, chunk) in self.body.chunks(16).enumerate() {
+                    let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    let ascii: String = chunk
+                        .iter()
+                        .map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' })
+                        .collect();
+                    text.push_str(&format!("{:08x}  {:<47}  |{}|\n", i * 16, hex.join(" "), ascii));
+                }
+            },
+        }
+        text
+    }
+}
+
+// A page that's been opened in the browser, along with the pages it was
+// reached from and any that were gone back from.
 pub struct Tab {
-    title: String,
-    url: Url,
-    content: StyledString,
+    pub title: String,
+    pub url: Option<Url>,
+    pub chain: Vec<GemtextToken>,
+    pub direction_hint: Option<Direction>,
+    // Set instead of chain when the page is an image.
+    pub image: Option<Rc<RgbaImage>>,
+    pub source: Source,
+    // Whether the source is shown instead of the page.
+    pub view_source: bool,
+    pub back: Vec<Url>,
+    pub forward: Vec<Url>,
+    pub info: Option<PageInfo>,
+    // The row the page was scrolled to when the tab was last shown.
+    pub scroll: usize,
+    // Set for a tab brought back from the last session until its page has
+    // been fetched again.
+    pub restored: bool,
+}
+
+impl Tab {
+    pub fn new() -> Tab {
+        Tab {
+            title: "New tab".to_owned(),
+            url: None,
+            chain: Vec::new(),
+            direction_hint: None,
+            image: None,
+            source: Source::default(),
+            view_source: false,
+            back: Vec::new(),
+            forward: Vec::new(),
+            info: None,
+            scroll: 0,
+            restored: false,
+        }
+    }
+
+    // Replaces the page shown in the tab. The title is taken from the page's
+    // first heading, falling back to its URL.
+    pub fn set_page(&mut self, url: Url, chain: Vec<GemtextToken>,
+                    direction_hint: Option<Direction>) {
+        self.title = chain
+            .iter()
+            .find(|token| token.kind == TokenKind::Heading)
+            .map(|token| token.display_text())
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| url.to_string());
+        self.url = Some(url);
+        self.chain = chain;
+        self.direction_hint = direction_hint;
+        self.image = None;
+        self.view_source = false;
+        self.scroll = 0;
+        self.restored = false;
+    }
+
+    // Shows an image in the tab, titled with its file name.
+    pub fn set_image(&mut self, url: Url, image: Rc<RgbaImage>) {
+        self.title = file_name(&url);
+        self.url = Some(url);
+        self.chain = Vec::new();
+        self.direction_hint = None;
+        self.image = Some(image);
+        self.view_source = false;
+        self.scroll = 0;
+        self.restored = false;
+    }
+}
+
+impl Default for Tab {
+    fn default() -> Self {
+        Tab::new()
+    }
+}
+
+// State shared by the whole browser, kept as the Cursive user data.
+pub struct Browser {
+    pub settings: Settings,
+    pub history: History,
+    pub bookmarks: Bookmarks,
+    pub known_hosts: KnownHosts,
+    pub downloads: Downloads,
+    pub keymap: Keymap,
+    // The start of a key sequence that's still being typed.
+    pub pending_keys: Vec<KeyPress>,
+    pub address_bar: AddressBarState,
+    pub command_line: CommandLineState,
+    pub tabs: Vec<Tab>,
+    // Index into tabs of the tab being shown.
+    pub current_tab: usize,
+}
+
+impl Browser {
+    pub fn new(settings: Settings, history: History, bookmarks: Bookmarks,
+               known_hosts: KnownHosts, keymap: Keymap) -> Browser {
+        Browser {
+            settings,
+            history,
+            bookmarks,
+            known_hosts,
+            downloads: Downloads::default(),
+            keymap,
+            pending_keys: Vec::new(),
+            address_bar: AddressBarState::default(),
+            command_line: CommandLineState::default(),
+            tabs: vec![Tab::new()],
+            current_tab: 0,
+        }
+    }
+
+    pub fn tab(&self) -> &Tab {
+        &self.tabs[self.current_tab]
+    }
+
+    pub fn tab_mut(&mut self) -> &mut Tab {
+        &mut self.tabs[self.current_tab]
+    }
+
+    // Every URL the user might want to go back to, for completion.
+    // Bookmarks come first, then pages from newest to oldest.
+    pub fn known_urls(&self) -> Vec<String> {
+        self.bookmarks
+            .entries()
+            .iter()
+            .map(|bookmark| bookmark.url.clone())
+            .chain(self.history.entries().iter().rev().map(|entry| entry.url.clone()))
+            .collect()
+    }
+
+    // Loads the client certificate configured for url, if there is one.
+    pub fn identity(&self, url: &Url) -> Result<Option<Identity>, IdentityError> {
+        identity_for(&self.settings.identities, url).map(load_identity).transpose()
+    }
+
+    // What the about: pages are built from.
+    pub fn about_sources(&self) -> AboutSources<'_> {
+        AboutSources {
+            settings: &self.settings,
+            keymap: &self.keymap,
+            known_hosts: &self.known_hosts,
+            bookmarks: &self.bookmarks,
+            history: &self.history,
+            downloads: &self.downloads,
+        }
+    }
+
+    // Opens an empty tab after the current one and switches to it.
+    pub fn open_tab(&mut self) {
+        self.current_tab += 1;
+        self.tabs.insert(self.current_tab, Tab::new());
+    }
+
+    // Closes the current tab, switching to the one before it. Closing the
+    // last tab leaves an empty one in its place.
+    pub fn close_tab(&mut self) {
+        self.tabs.remove(self.current_tab);
+        if self.tabs.is_empty() {
+            self.tabs.push(Tab::new());
+        }
+        self.current_tab = self.current_tab.saturating_sub(1);
+    }
+
+    // The open tabs, to be saved when armstrong quits. Empty tabs are left
+    // out.
+    pub fn session(&self) -> Session {
+        let mut session = Session::default();
+        for (i, tab) in self.tabs.iter().enumerate() {
+            let url = match &tab.url {
+                Some(url) => url.to_string(),
+                None => continue,
+            };
+            if i == self.current_tab {
+                session.current_tab = session.tabs.len();
+            }
+            session.tabs.push(TabSession {
+                title: tab.title.clone(),
+                url,
+                scroll: tab.scroll,
+                back: tab.back.iter().map(|url| url.to_string()).collect(),
+                forward: tab.forward.iter().map(|url| url.to_string()).collect(),
+            });
+        }
+        session
+    }
+
+    // Replaces the tabs with those of a saved session. Their pages aren't
+    // fetched until they're shown. Returns whether there was anything to
+    // restore.
+    pub fn restore(&mut self, session: &Session) -> bool {
+        let parse = |urls: &[String]| urls.iter().filter_map(|url| Url::parse(url).ok()).collect();
+        let tabs: Vec<Tab> = session.tabs
+            .iter()
+            .filter_map(|saved| {
+                let url = Url::parse(&saved.url).ok()?;
+                let title = if saved.title.is_empty() { url.to_string() } else { saved.title.clone() };
+                Some(Tab {
+                    title,
+                    url: Some(url),
+                    back: parse(&saved.back),
+                    forward: parse(&saved.forward),
+                    scroll: saved.scroll,
+                    restored: true,
+                    ..Tab::new()
+                })
+            })
+            .collect();
+        if tabs.is_empty() {
+            return false;
+        }
+        self.current_tab = session.current_tab.min(tabs.len() - 1);
+        self.tabs = tabs;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+
+    #[test]
+    fn tab_title_comes_from_first_heading() {
+        let url = Url::parse("gemini://example.org/").unwrap();
+        let mut tab = Tab::new();
+        tab.set_page(url.clone(), parse_gemtext("## Sub\n# Title\n"), None);
+        assert_eq!(tab.title, "Title");
+        tab.set_page(url, parse_gemtext("No headings here\n"), None);
+        assert_eq!(tab.title, "gemini://example.org/");
+    }
+
+    #[test]
+    fn source_is_numbered_or_dumped() {
+        let mut source = Source {
+            header: "20 text/gemini".to_owned(),
+            body: (1..=10).map(|i| format!("line {}\n", i)).collect::<String>().into_bytes(),
+        };
+        let text = source.text();
+        assert!(text.starts_with("20 text/gemini\n\n 1  line 1\n"));
+        assert!(text.ends_with("10  line 10\n"));
+        source.body = vec![0x89, b'P', b'N', b'G', 0xff];
+        let dump = format!("00000000  {:<47}  |.PNG.|\n", "89 50 4e 47 ff");
+        assert!(source.text().ends_with(&dump));
+    }
+
+    #[test]
+    fn page_info_sums_up_the_response() {
+        let mut info = PageInfo {
+            status: 20,
+            meta: "text/gemini; lang=en".to_owned(),
+            mimetype: "text/gemini".to_owned(),
+            charset: "utf-8".to_owned(),
+            size: 1536,
+            elapsed: Duration::from_millis(85),
+            trust: Some(TrustState::Known),
+            identity: None,
+        };
+        assert_eq!(info.summary(),
+            "20 text/gemini; lang=en · utf-8 · 1.5 KB · 85 ms · cert known · no identity");
+        info.status = 51;
+        info.meta = "Not found".to_owned();
+        info.size = 12;
+        info.trust = None;
+        info.identity = Some("me".to_owned());
+        assert_eq!(info.summary(), "51 Not found · 12 B · 85 ms · identity me");
+    }
+
+    #[test]
+    fn sessions_save_tabs_with_their_history() {
+        let mut browser = Browser::new(Settings::default(), History::in_memory(),
+                                       Bookmarks::default(), KnownHosts::default(),
+                                       Keymap::default());
+        let page = |path: &str| Url::parse(&format!("gemini://example.org/{}", path)).unwrap();
+        browser.tab_mut().set_page(page("b"), parse_gemtext("# B\n"), None);
+        browser.tab_mut().back.push(page("a"));
+        browser.tab_mut().scroll = 12;
+        browser.open_tab();
+        browser.open_tab();
+        browser.tab_mut().set_page(page("c"), Vec::new(), None);
+
+        let session = browser.session();
+        assert_eq!(session.tabs.len(), 2);
+        assert_eq!(session.current_tab, 1);
+        assert_eq!(session.tabs[0].title, "B");
+        assert_eq!(session.tabs[0].back, vec!["gemini://example.org/a"]);
+
+        let mut restored = Browser::new(Settings::default(), History::in_memory(),
+                                        Bookmarks::default(), KnownHosts::default(),
+                                        Keymap::default());
+        assert!(!restored.restore(&Session::default()));
+        assert!(restored.restore(&session));
+        assert_eq!(restored.tabs.len(), 2);
+        assert_eq!(restored.current_tab, 1);
+        let tab = &restored.tabs[0];
+        assert!(tab.restored);
+        assert_eq!((tab.url.clone(), tab.scroll), (Some(page("b")), 12));
+        assert_eq!(tab.back, vec![page("a")]);
+        assert_eq!(restored.session(), session);
+    }
 }
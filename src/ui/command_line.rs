@@ -0,0 +1,255 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use cursive::Cursive;
+use cursive::event::{EventTrigger, Key};
+use cursive::view::{Nameable, Resizable};
+use cursive::views::{
+    Dialog,
+    EditView,
+    HideableView,
+    LinearLayout,
+    NamedView,
+    OnEventView,
+    TextView,
+};
+use url::Url;
+
+use crate::address::parse_address;
+use crate::commands::{complete, parse_command, Command};
+use crate::settings::{load_keymap, load_theme};
+use crate::ui::browser::Browser;
+use crate::ui::completions::Completions;
+use crate::ui::downloads::download;
+use crate::ui::page_view::PageView;
+use crate::ui::tui::{
+    apply_theme,
+    bookmark_page,
+    open_in_new_tab,
+    open_url,
+    run_action,
+    swallow_keys,
+};
+
+// Where the command line is in its history and completions, reset whenever
+// it's opened.
+#[derive(Clone, Debug, Default)]
+pub struct CommandLineState {
+    pub history: Vec<String>,
+    // Index into history of the line being shown, if one was recalled.
+    recalled: Option<usize>,
+    completions: Completions,
+}
+
+// The line below the page that commands are typed into, hidden until ':'.
+pub fn command_bar() -> NamedView<HideableView<LinearLayout>> {
+    let command_box = OnEventView::new(
+        EditView::new()
+            .on_submit(submit)
+            .with_name("command_box"))
+        .on_event(Key::Esc, close_command_line)
+        .on_event(Key::Tab, complete_command)
+        .on_event(Key::Up, |s| recall(s, true))
+        .on_event(Key::Down, |s| recall(s, false))
+        .on_event_inner(EventTrigger::any(), |_, event| swallow_keys(event));
+
+    HideableView::new(
+        LinearLayout::horizontal()
+        .child(TextView::new(" :"))
+        .child(command_box.full_width())
+        .child(TextView::new("").with_name("command_status"))
+    )
+    .hidden()
+    .with_name("command_bar")
+}
+
+pub fn open_command_line(app: &mut Cursive) {
+    if let Some(browser) = app.user_data::<Browser>() {
+        let state = &mut browser.command_line;
+        state.recalled = None;
+        state.completions.clear();
+    }
+    set_line(app, "");
+    set_status(app, "");
+    app.call_on_name("command_bar", |view: &mut HideableView<LinearLayout>| {
+        view.unhide()
+    });
+    let _ = app.focus_name("command_box");
+}
+
+fn close_command_line(app: &mut Cursive) {
+    app.call_on_name("command_bar", |view: &mut HideableView<LinearLayout>| {
+        view.hide()
+    });
+    let _ = app.focus_name("page");
+}
+
+fn set_line(app: &mut Cursive, line: &str) {
+    app.call_on_name("command_box", |view: &mut EditView| {
+        view.set_content(line);
+    });
+}
+
+fn set_status(app: &mut Cursive, status: &str) {
+    let status = status.to_owned();
+    app.call_on_name("command_status", |view: &mut TextView| view.set_content(status));
+}
+
+fn submit(app: &mut Cursive, line: &str) {
+    let line = line.trim().to_owned();
+    close_command_line(app);
+    if line.is_empty() {
+        return;
+    }
+    if let Some(browser) = app.user_data::<Browser>() {
+        let history = &mut browser.command_line.history;
+        history.retain(|previous| *previous != line);
+        history.push(line.clone());
+    }
+    if let Err(error) = run_command(app, &line) {
+        app.add_layer(Dialog::info(error).title("Command failed"));
+    }
+}
+
+// Steps through the lines run before, older ones first when older is set.
+fn recall(app: &mut Cursive, older: bool) {
+    let line = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let state = &mut browser.command_line;
+            let len = state.history.len();
+            state.recalled = match state.recalled {
+                _ if len == 0 => None,
+                None if older => Some(len - 1),
+                None => None,
+                Some(i) if older => Some(i.saturating_sub(1)),
+                Some(i) if i + 1 < len => Some(i + 1),
+                Some(_) => None,
+            };
+            state.recalled.map(|i| state.history[i].clone()).unwrap_or_default()
+        },
+        None => return,
+    };
+    set_line(app, &line);
+}
+
+// Completes the line, or moves on to the next completion when the line is
+// still the one completed last time.
+fn complete_command(app: &mut Cursive) {
+    let line = app
+        .call_on_name("command_box", |view: &mut EditView| view.get_content())
+        .map(|line| line.to_string())
+        .unwrap_or_default();
+    let (completed, status) = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let urls = browser.known_urls();
+            let completions = &mut browser.command_line.completions;
+            let completed = completions.next(&line, || complete(&line, &urls)).map(str::to_owned);
+            (completed.unwrap_or(line), format!("{} ", completions.status()))
+        },
+        None => return,
+    };
+    set_line(app, &completed);
+    set_status(app, &status);
+}
+
+fn parse_url(app: &mut Cursive, url: &str) -> Result<Url, String> {
+    let search_url = app.user_data::<Browser>()
+        .map(|browser| browser.settings.general.search_url.clone())
+        .unwrap_or_default();
+    parse_address(url, &search_url).map_err(|e| e.to_string())
+}
+
+// Changes a setting and applies it straight away. Settings that don't load,
+// like a theme that doesn't exist, are put back the way they were.
+fn set_setting(app: &mut Cursive, key: &str, value: &str) -> Result<(), String> {
+    let browser = match app.user_data::<Browser>() {
+        Some(browser) => browser,
+        None => return Ok(()),
+    };
+    let mut settings = browser.settings.clone();
+    settings.set(key, value).map_err(|e| e.to_string())?;
+    let theme = load_theme(&settings.theme).map_err(|e| e.to_string())?;
+    let keymap = load_keymap(&settings.keys).map_err(|e| e.to_string())?;
+    browser.settings = settings.clone();
+    browser.keymap = keymap;
+
+    if key.starts_with("theme.") {
+        apply_theme(app, theme);
+    }
+    app.call_on_name("page", |page: &mut PageView| page.set_settings(&settings.display));
+    Ok(())
+}
+
+// Most files :source can have open at once, counting the one typed.
+const MAX_SOURCE_DEPTH: usize = 16;
+
+// Runs every line of a file as a command, skipping blank lines and lines
+// starting with #. Stops at the first line that fails. sourcing holds the
+// files already being sourced, so a file that sources itself, directly or
+// through others, is an error rather than endless recursion.
+fn source(app: &mut Cursive, path: &str, sourcing: &mut Vec<PathBuf>) -> Result<(), String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| Path::new(path).to_owned());
+    if sourcing.contains(&canonical) {
+        return Err(format!("{} is already being sourced", path));
+    }
+    if sourcing.len() >= MAX_SOURCE_DEPTH {
+        return Err(format!("Files are sourced more than {} deep", MAX_SOURCE_DEPTH));
+    }
+    sourcing.push(canonical);
+    let mut result = Ok(());
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        result = run_sourced_command(app, line, sourcing)
+            .map_err(|e| format!("{}:{}: {}", path, i + 1, e));
+        if result.is_err() {
+            break;
+        }
+    }
+    sourcing.pop();
+    result
+}
+
+pub fn run_command(app: &mut Cursive, line: &str) -> Result<(), String> {
+    run_sourced_command(app, line, &mut Vec::new())
+}
+
+fn run_sourced_command(app: &mut Cursive, line: &str, sourcing: &mut Vec<PathBuf>)
+    -> Result<(), String> {
+    let command = parse_command(line).map_err(|e| e.to_string())?;
+    match command {
+        Command::Open(url) => {
+            let url = parse_url(app, &url)?;
+            open_url(app, url);
+        },
+        Command::TabOpen(url) => {
+            let url = parse_url(app, &url)?;
+            open_in_new_tab(app, url);
+        },
+        Command::Bookmark(None) => bookmark_page(app),
+        Command::Bookmark(Some(url)) => {
+            let url = parse_url(app, &url)?;
+            if let Some(browser) = app.user_data::<Browser>() {
+                browser.bookmarks.add(&url, "");
+            }
+        },
+        Command::Set(key, value) => set_setting(app, &key, &value)?,
+        Command::Download(url) => {
+            let url = match url {
+                Some(url) => parse_url(app, &url)?,
+                None => match app.user_data::<Browser>().and_then(|b| b.tab().url.clone()) {
+                    Some(url) => url,
+                    None => return Err("Nothing to download".to_owned()),
+                },
+            };
+            download(app, url, None)?;
+        },
+        Command::Source(path) => source(app, &path, sourcing)?,
+        Command::Action(action) => run_action(app, action),
+    }
+    Ok(())
+}
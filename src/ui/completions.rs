@@ -0,0 +1,57 @@
+// The completions offered for what was last typed into a line, shared by
+// the address bar and the command line. Pressing tab again while the line
+// is still the completion shown moves on to the next one.
+#[derive(Clone, Debug, Default)]
+pub struct Completions {
+    list: Vec<String>,
+    current: usize,
+}
+
+impl Completions {
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.current = 0;
+    }
+
+    // The completion to put in place of line. candidates is only asked for
+    // when line isn't the completion shown last.
+    pub fn next<F>(&mut self, line: &str, candidates: F) -> Option<&str>
+    where
+        F: FnOnce() -> Vec<String>,
+    {
+        if self.list.get(self.current).is_some_and(|shown| shown == line) {
+            self.current = (self.current + 1) % self.list.len();
+        } else {
+            self.list = candidates();
+            self.current = 0;
+        }
+        self.list.get(self.current).map(String::as_str)
+    }
+
+    // Which completion is shown, like 2/5.
+    pub fn status(&self) -> String {
+        if self.list.is_empty() {
+            "No completions".to_owned()
+        } else {
+            format!("{}/{}", self.current + 1, self.list.len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tab_cycles_through_the_completions() {
+        let mut completions = Completions::default();
+        let candidates = || vec!["open".to_owned(), "open_tab".to_owned()];
+        assert_eq!(completions.next("op", candidates), Some("open"));
+        assert_eq!(completions.status(), "1/2");
+        assert_eq!(completions.next("open", || unreachable!()), Some("open_tab"));
+        assert_eq!(completions.next("open_tab", || unreachable!()), Some("open"));
+
+        assert_eq!(completions.next("x", Vec::new), None);
+        assert_eq!(completions.status(), "No completions");
+    }
+}
@@ -0,0 +1,268 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use cursive::Cursive;
+use cursive::event::Key;
+use cursive::view::{Nameable, Resizable};
+use cursive::views::{Dialog, EditView, OnEventView};
+use url::Url;
+
+use crate::downloads::{downloads_page, file_name, open_file, part_path, save, unique_path};
+use crate::gemtext::parse_gemtext;
+use crate::handlers::temp_path;
+use crate::settings::HandlerSettings;
+use crate::transaction::tofu::host_key;
+use crate::transaction::visit::{open_gemini, ResponseStream};
+use crate::ui::browser::Browser;
+use crate::ui::handlers::open_saved;
+use crate::ui::tui::{refresh_page, update_status};
+
+// The page listing this session's downloads.
+pub const DOWNLOADS_URL: &str = "about:downloads";
+
+// How often a running download reports how far it's got.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+// A free path in the download directory for url.
+fn download_path(app: &mut Cursive, url: &Url) -> Option<PathBuf> {
+    let dir = app.user_data::<Browser>()?.settings.downloads.download_dir();
+    Some(unique_path(&dir, &file_name(url)))
+}
+
+// Asks where to save url, suggesting a free name in the download directory.
+pub fn save_as_dialog(app: &mut Cursive, url: Url) {
+    let path = match download_path(app, &url) {
+        Some(path) => path,
+        None => return,
+    };
+    path_dialog(app, "Save as", path, move |s, path| download(s, url.clone(), Some(path)));
+}
+
+// Asks where to save the source of the current page and writes it there.
+pub fn save_source_dialog(app: &mut Cursive) {
+    let page = app.user_data::<Browser>()
+        .and_then(|browser| browser.tab().url.clone().map(|url| (url, browser.tab().source.body.clone())));
+    let (url, body) = match page {
+        Some(page) => page,
+        None => return,
+    };
+    let path = match download_path(app, &url) {
+        Some(path) => path,
+        None => return,
+    };
+    path_dialog(app, "Save source as", path, move |s, path| {
+        path.parent()
+            .map(fs::create_dir_all)
+            .unwrap_or(Ok(()))
+            .and_then(|_| fs::write(&path, &body))
+            .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))?;
+        s.add_layer(Dialog::info(format!("Saved {}", path.display())));
+        Ok(())
+    });
+}
+
+// Asks for a path, starting from path, and hands it to on_save.
+fn path_dialog<F>(app: &mut Cursive, title: &str, path: PathBuf, on_save: F)
+where
+    F: Fn(&mut Cursive, PathBuf) -> Result<(), String> + 'static,
+{
+    let on_save = Rc::new(on_save);
+    let submit = move |s: &mut Cursive, path: &str| {
+        s.pop_layer();
+        if let Err(error) = on_save(s, PathBuf::from(path)) {
+            s.add_layer(Dialog::info(error));
+        }
+    };
+    let path_box = EditView::new()
+        .content(path.to_string_lossy())
+        .on_submit(submit.clone())
+        .with_name("save_as_path")
+        .min_width(50);
+
+    app.add_layer(
+        OnEventView::new(
+            Dialog::around(path_box)
+            .title(title)
+            .button("Save", move |s| {
+                let path = s.call_on_name("save_as_path", |view: &mut EditView| {
+                    view.get_content()
+                });
+                if let Some(path) = path {
+                    submit(s, &path);
+                }
+            })
+            .dismiss_button("Cancel"))
+        .on_event(Key::Esc, |s| {
+            s.pop_layer();
+        }));
+}
+
+// Downloads url in the background, to path or a free path in the download
+// directory named after url.
+pub fn download(app: &mut Cursive, url: Url, path: Option<PathBuf>) -> Result<(), String> {
+    let (identity, known) = match app.user_data::<Browser>() {
+        Some(browser) => (
+            browser.identity(&url).map_err(|e| e.to_string())?,
+            browser.known_hosts.get(&host_key(&url)).map(|known| known.to_owned()),
+        ),
+        None => return Ok(()),
+    };
+    let request = url.clone();
+    start(app, url, path, None, move || {
+        open_gemini(&request, identity.as_ref(), known.as_deref()).map_err(|e| e.to_string())
+    })
+}
+
+// Saves the body of a response that's already been requested, like a page
+// that turned out not to be text.
+pub fn download_stream(app: &mut Cursive, url: Url, stream: ResponseStream)
+    -> Result<(), String> {
+    start(app, url, None, None, move || Ok(stream))
+}
+
+// Saves the body of a response that's already been requested to a temporary
+// file, and opens it with handler once it's all there.
+pub fn open_stream_with(app: &mut Cursive, url: Url, stream: ResponseStream,
+                        handler: HandlerSettings) -> Result<(), String> {
+    let path = temp_path(&file_name(&url)).map_err(|e| format!("Couldn't save {}: {}", url, e))?;
+    start(app, url, Some(path), Some(handler), move || Ok(stream))
+}
+
+// Creates the .part file and starts a thread that fills it with the body of
+// the response from connect, renaming it once the body is all there and
+// opening it with handler if there is one. A failed download leaves its
+// .part file behind.
+fn start<F>(app: &mut Cursive, url: Url, path: Option<PathBuf>, handler: Option<HandlerSettings>,
+            connect: F) -> Result<(), String>
+where
+    F: FnOnce() -> Result<ResponseStream, String> + Send + 'static,
+{
+    let path = match path.or_else(|| download_path(app, &url)) {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let part = part_path(&path);
+    let mut file = path.parent()
+        .map(fs::create_dir_all)
+        .unwrap_or(Ok(()))
+        .and_then(|_| File::create(&part))
+        .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))?;
+    let id = match app.user_data::<Browser>() {
+        Some(browser) => browser.downloads.start(&url, &path),
+        None => return Ok(()),
+    };
+    refresh(app);
+
+    let sink = app.cb_sink().clone();
+    thread::spawn(move || {
+        let mut last_report = Instant::now();
+        let result = connect()
+            .and_then(|mut stream| {
+                let response = &stream.response;
+                if !(20..=29).contains(&response.status) {
+                    return Err(format!("{} {}", response.status, response.meta));
+                }
+                save(&mut stream, &mut file, |received| {
+                    if last_report.elapsed() >= PROGRESS_INTERVAL {
+                        last_report = Instant::now();
+                        let _ = sink.send(Box::new(move |s| on_progress(s, id, received)));
+                    }
+                })
+                .map_err(|e| e.to_string())
+            })
+            .and_then(|received| {
+                fs::rename(&part, &path)
+                    .map(|_| received)
+                    .map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+            });
+        let _ = sink.send(Box::new(move |s| on_finished(s, id, result, handler)));
+    });
+    Ok(())
+}
+
+fn on_progress(app: &mut Cursive, id: usize, received: u64) {
+    if let Some(browser) = app.user_data::<Browser>() {
+        browser.downloads.set_received(id, received);
+    }
+    refresh(app);
+}
+
+fn on_finished(app: &mut Cursive, id: usize, result: Result<u64, String>,
+               handler: Option<HandlerSettings>) {
+    let (failed, path, name) = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let failed = result.as_ref().err().cloned();
+            browser.downloads.finish(id, result);
+            match browser.downloads.get(id) {
+                Some(download) => (failed, download.path.clone(), download.name()),
+                None => return,
+            }
+        },
+        None => return,
+    };
+    refresh(app);
+    let result = match (failed, handler) {
+        (Some(error), _) => Err(format!("Couldn't download {}: {}", name, error)),
+        (None, Some(handler)) => open_saved(app, &path, &handler),
+        (None, None) => {
+            app.add_layer(Dialog::info(format!("Saved {}", path.display())));
+            Ok(())
+        },
+    };
+    if let Err(error) = result {
+        app.add_layer(Dialog::info(error));
+    }
+}
+
+// Shows the latest progress in the status bar, and on the downloads page if
+// it's open. Only the page is rebuilt, so the address bar, any search and the
+// selected link stay as they are.
+fn refresh(app: &mut Cursive) {
+    update_status(app);
+    let showing_downloads = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let page = downloads_page(&browser.downloads);
+            let tab = browser.tab_mut();
+            let showing = tab.url.as_ref().is_some_and(|url| url.as_str() == DOWNLOADS_URL);
+            if showing {
+                tab.chain = parse_gemtext(&page);
+                tab.source.body = page.into_bytes();
+            }
+            showing
+        },
+        None => false,
+    };
+    if showing_downloads {
+        refresh_page(app);
+    }
+}
+
+// Carries out a link on the downloads page, like about:downloads?open=3,
+// which opens the file of download 3.
+pub fn downloads_action(app: &mut Cursive, query: &str) -> Result<(), String> {
+    let (action, id) = query.split_once('=').ok_or("Unknown downloads action")?;
+    let id: usize = id.parse().map_err(|_| format!("No download {}", id))?;
+    let browser = match app.user_data::<Browser>() {
+        Some(browser) => browser,
+        None => return Ok(()),
+    };
+    let download = browser.downloads.get(id).ok_or(format!("No download {}", id))?;
+    match action {
+        "open" => open_file(&download.path),
+        "delete" => {
+            let path = download.path.clone();
+            browser.downloads.remove(id);
+            for path in [part_path(&path), path] {
+                if path.exists() {
+                    fs::remove_file(&path)
+                        .map_err(|e| format!("Couldn't delete {}: {}", path.display(), e))?;
+                }
+            }
+            Ok(())
+        },
+        _ => Err(format!("Unknown downloads action {}", action)),
+    }
+}
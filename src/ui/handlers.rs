@@ -0,0 +1,103 @@
+use std::cell::RefCell;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use cursive::Cursive;
+use cursive::event::Key;
+use cursive::view::{Nameable, Resizable};
+use cursive::views::{Checkbox, Dialog, EditView, LinearLayout, OnEventView, TextView};
+use url::Url;
+
+use crate::handlers::{handler_args, handler_for, run_in_terminal, spawn_handler};
+use crate::settings::HandlerSettings;
+use crate::transaction::visit::ResponseStream;
+use crate::ui::browser::Browser;
+use crate::ui::downloads::{download_stream, open_stream_with};
+
+// Asks what to do with a response that can't be shown as a page: open it
+// with a program, starting with the one configured for its type, or save it.
+pub fn open_with_dialog(app: &mut Cursive, url: Url, stream: ResponseStream) {
+    let mimetype = stream.response.mimetype.clone();
+    let handler = app.user_data::<Browser>()
+        .and_then(|browser| handler_for(&browser.settings.handlers, &mimetype).cloned())
+        .unwrap_or_default();
+    // The connection is held open while the choice is made.
+    let stream = Rc::new(RefCell::new(Some(stream)));
+    let open_stream = stream.clone();
+    let open_url = url.clone();
+    let open = Rc::new(move |s: &mut Cursive| {
+        let command = s
+            .call_on_name("open_with_command", |view: &mut EditView| view.get_content())
+            .map(|command| command.to_string())
+            .unwrap_or_default();
+        let terminal = s
+            .call_on_name("open_with_terminal", |view: &mut Checkbox| view.is_checked())
+            .unwrap_or(false);
+        s.pop_layer();
+        if let Some(stream) = open_stream.borrow_mut().take() {
+            let handler = HandlerSettings { command, terminal };
+            if let Err(error) = open_with(s, &open_url, stream, &handler) {
+                s.add_layer(Dialog::info(error));
+            }
+        }
+    });
+    let submit = open.clone();
+
+    let mut terminal = Checkbox::new();
+    terminal.set_checked(handler.terminal);
+    let form = LinearLayout::vertical()
+        .child(TextView::new(format!("{} is {}. Open it with:", url, mimetype)))
+        .child(EditView::new()
+            .content(handler.command)
+            .on_submit(move |s, _| submit(s))
+            .with_name("open_with_command")
+            .min_width(50))
+        .child(LinearLayout::horizontal()
+            .child(terminal.with_name("open_with_terminal"))
+            .child(TextView::new(" Run in the terminal")));
+
+    app.add_layer(
+        OnEventView::new(
+            Dialog::around(form)
+            .title("Open with")
+            .button("Open", move |s| open(s))
+            .button("Save", move |s| {
+                s.pop_layer();
+                if let Some(stream) = stream.borrow_mut().take() {
+                    if let Err(error) = download_stream(s, url.clone(), stream) {
+                        s.add_layer(Dialog::info(error));
+                    }
+                }
+            })
+            .dismiss_button("Cancel"))
+        .on_event(Key::Esc, |s| {
+            s.pop_layer();
+        }));
+}
+
+// Reads the rest of the body into a temporary file in the background, the
+// way downloads are, and opens it with handler once it's there.
+fn open_with(app: &mut Cursive, url: &Url, stream: ResponseStream, handler: &HandlerSettings)
+    -> Result<(), String> {
+    if handler.command.trim().is_empty() {
+        return Err("No command given".to_owned());
+    }
+    open_stream_with(app, url.clone(), stream, handler.clone())
+}
+
+// Opens the file at path with handler. A program run in the terminal is
+// done with the file when it exits, so the file goes then; others may still
+// be reading it, so theirs stay until armstrong quits.
+pub fn open_saved(app: &mut Cursive, path: &Path, handler: &HandlerSettings)
+    -> Result<(), String> {
+    let args = handler_args(&handler.command, path);
+    if handler.terminal {
+        let result = run_in_terminal(&args);
+        let _ = fs::remove_file(path);
+        app.clear();
+        result
+    } else {
+        spawn_handler(&args)
+    }
+}
@@ -0,0 +1,796 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use cursive::event::{Event, EventResult, Key, MouseButton, MouseEvent};
+use cursive::theme::{Color, ColorStyle, ColorType};
+use cursive::{Cursive, Printer, Rect, Vec2, View};
+use image::RgbaImage;
+use url::Url;
+
+use crate::gemtext::{outline, GemtextToken, OutlineEntry, TokenKind};
+use crate::image_art::{image_rows, to_256_colors, ColorDepth, HalfBlock};
+use crate::layout::{layout_gemtext, text_width, Direction, LayoutLine, LayoutOptions};
+use crate::links::PageLink;
+use crate::search::{find_matches, SearchError, SearchMatch};
+use crate::settings::{DisplaySettings, SearchSettings};
+use crate::theme::{Element, Theme};
+use crate::ui::styles::element_style;
+
+// Called with a link's URL when it's picked in hint mode, and whether it
+// should be opened in a new tab.
+pub type FollowCallback = Rc<dyn Fn(&mut Cursive, &Url, bool)>;
+
+// Called with a link's URL and text when it's right clicked.
+pub type MenuCallback = Rc<dyn Fn(&mut Cursive, &Url, &str)>;
+
+// A label shown over a link while in hint mode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hint {
+    pub label: String,
+    // The row the label is drawn on, the link's first visible row.
+    pub row: usize,
+    pub url: Url,
+}
+
+struct HintMode {
+    hints: Vec<Hint>,
+    typed: String,
+    new_tab: bool,
+}
+
+struct Search {
+    query: String,
+    options: SearchSettings,
+    matches: Vec<SearchMatch>,
+    // Index into matches of the one last jumped to.
+    current: Option<usize>,
+}
+
+// Numbers links 1 to count. When there are more than nine the labels are
+// zero padded so every label is the same length and none is a prefix of
+// another.
+pub fn hint_labels(count: usize) -> Vec<String> {
+    let width = count.to_string().len();
+    (1..=count)
+        .map(|n| format!("{:0width$}", n, width = width))
+        .collect()
+}
+
+// Draws a parsed gemtext page, reflowing it whenever the space available to
+// it changes.
+pub struct PageView {
+    chain: Vec<GemtextToken>,
+    // The links in chain, ordered by token index.
+    links: Vec<PageLink>,
+    lines: Vec<LayoutLine>,
+    // An image shown below the lines, and how it's drawn at the current
+    // width.
+    image: Option<Rc<RgbaImage>>,
+    image_rows: Vec<Vec<HalfBlock>>,
+    settings: DisplaySettings,
+    color_depth: ColorDepth,
+    theme: Theme,
+    direction_hint: Option<Direction>,
+    // The width the current lines were laid out for.
+    layout_width: Option<usize>,
+    // Set when the content changes so parent views don't reuse cached sizes.
+    dirty: bool,
+    // The rows shown by the last draw, as a start and end row.
+    visible: Cell<(usize, usize)>,
+    hint_mode: Option<HintMode>,
+    on_follow: Option<FollowCallback>,
+    on_menu: Option<MenuCallback>,
+    search: Option<Search>,
+    // Index into links of the link under the cursor.
+    selected_link: Option<usize>,
+    // The link a mouse button went down on, which it has to come up on too.
+    pressed_link: Option<usize>,
+}
+
+impl PageView {
+    pub fn new(settings: &DisplaySettings, theme: Theme) -> Self {
+        PageView {
+            chain: Vec::new(),
+            links: Vec::new(),
+            lines: Vec::new(),
+            image: None,
+            image_rows: Vec::new(),
+            settings: settings.clone(),
+            color_depth: ColorDepth::from_setting(&settings.image_colors),
+            theme,
+            direction_hint: None,
+            layout_width: None,
+            dirty: true,
+            visible: Cell::new((0, 0)),
+            hint_mode: None,
+            on_follow: None,
+            on_menu: None,
+            search: None,
+            selected_link: None,
+            pressed_link: None,
+        }
+    }
+
+    pub fn set_content(&mut self, chain: Vec<GemtextToken>, links: Vec<PageLink>) {
+        self.chain = chain;
+        self.links = links;
+        self.image = None;
+        self.hint_mode = None;
+        self.search = None;
+        self.selected_link = None;
+        self.pressed_link = None;
+        self.layout_width = None;
+        self.dirty = true;
+    }
+
+    // Replaces the lines of a page that's changed while being shown, like
+    // about:downloads, keeping the search and the selected link. Hints are
+    // dropped if the links they were given to have changed.
+    pub fn update_content(&mut self, chain: Vec<GemtextToken>, links: Vec<PageLink>) {
+        let same_links = links.len() == self.links.len()
+            && links.iter().zip(&self.links).all(|(a, b)| a.url == b.url);
+        if !same_links {
+            self.hint_mode = None;
+            self.pressed_link = None;
+            self.selected_link = self.selected_link.filter(|&i| i < links.len());
+        }
+        self.chain = chain;
+        self.links = links;
+        self.layout_width = None;
+        self.dirty = true;
+    }
+
+    // Shows image below the page, scaled down to fit its width.
+    pub fn set_image(&mut self, image: Option<Rc<RgbaImage>>) {
+        self.image = image;
+        self.layout_width = None;
+        self.dirty = true;
+    }
+
+    pub fn links(&self) -> &[PageLink] {
+        &self.links
+    }
+
+    pub fn link_for_token(&self, token: usize) -> Option<&PageLink> {
+        self.links
+            .binary_search_by_key(&token, |link| link.token)
+            .ok()
+            .map(|i| &self.links[i])
+    }
+
+    // Picks the theme element for a row, links depending on where they lead
+    // and whether they've been visited.
+    fn element_for_line(&self, line: &LayoutLine) -> Element {
+        if line.kind != TokenKind::Link {
+            return Element::for_token(line.kind);
+        }
+        match self.link_for_token(line.token) {
+            Some(link) if link.visited => Element::VisitedLink,
+            Some(link) if link.kind.is_external() => Element::ExternalLink,
+            _ => Element::Link,
+        }
+    }
+
+    // Sets the base direction suggested by the lang parameter of the page.
+    pub fn set_direction_hint(&mut self, direction_hint: Option<Direction>) {
+        self.direction_hint = direction_hint;
+        self.layout_width = None;
+        self.dirty = true;
+    }
+
+    pub fn set_settings(&mut self, settings: &DisplaySettings) {
+        self.settings = settings.clone();
+        self.color_depth = ColorDepth::from_setting(&settings.image_colors);
+        self.layout_width = None;
+        self.dirty = true;
+    }
+
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    pub fn set_on_follow<F>(&mut self, on_follow: F)
+    where
+        F: Fn(&mut Cursive, &Url, bool) + 'static,
+    {
+        self.on_follow = Some(Rc::new(on_follow));
+    }
+
+    pub fn set_on_menu<F>(&mut self, on_menu: F)
+    where
+        F: Fn(&mut Cursive, &Url, &str) + 'static,
+    {
+        self.on_menu = Some(Rc::new(on_menu));
+    }
+
+    // The index into links of the link drawn at position, if it leads
+    // somewhere. Clicks past the end of the link's text miss it.
+    pub fn link_at(&self, position: Vec2) -> Option<usize> {
+        let line = self.lines.get(position.y)?;
+        if line.kind != TokenKind::Link || position.x >= text_width(&line.text) {
+            return None;
+        }
+        let i = self.links.binary_search_by_key(&line.token, |link| link.token).ok()?;
+        self.links[i].url.as_ref().map(|_| i)
+    }
+
+    // Left click follows a link, middle click opens it in a new tab and right
+    // click asks what to do with it. The link is selected on press and acted
+    // on when the button is released over it; pressed is the link the button
+    // went down on.
+    fn on_mouse_event(&mut self, position: Vec2, event: MouseEvent, pressed: Option<usize>)
+        -> EventResult {
+        let i = match self.link_at(position) {
+            Some(i) => i,
+            None => return EventResult::Ignored,
+        };
+        if let MouseEvent::Release(_) = event {
+            if pressed != Some(i) {
+                return EventResult::Ignored;
+            }
+        }
+        let url = match &self.links[i].url {
+            Some(url) => url.clone(),
+            None => return EventResult::Ignored,
+        };
+        match event {
+            MouseEvent::Press(_) => {
+                self.selected_link = Some(i);
+                self.pressed_link = Some(i);
+                EventResult::Consumed(None)
+            },
+            MouseEvent::Release(MouseButton::Left) | MouseEvent::Release(MouseButton::Middle) => {
+                let new_tab = event == MouseEvent::Release(MouseButton::Middle);
+                match self.on_follow.clone() {
+                    Some(on_follow) => EventResult::with_cb(move |s| on_follow(s, &url, new_tab)),
+                    None => EventResult::Consumed(None),
+                }
+            },
+            MouseEvent::Release(MouseButton::Right) => {
+                let text = self.chain[self.links[i].token].display_text();
+                match self.on_menu.clone() {
+                    Some(on_menu) => EventResult::with_cb(move |s| on_menu(s, &url, &text)),
+                    None => EventResult::Consumed(None),
+                }
+            },
+            _ => EventResult::Ignored,
+        }
+    }
+
+    // Labels every link on the rows that were last drawn. Returns false when
+    // there are none, in which case hint mode isn't entered.
+    pub fn start_hints(&mut self, new_tab: bool) -> bool {
+        let (start, end) = self.visible.get();
+        let mut targets: Vec<(usize, Url)> = Vec::new();
+        let mut last_token = None;
+        for (row, line) in self.lines.iter().enumerate().take(end).skip(start) {
+            if line.kind != TokenKind::Link || last_token == Some(line.token) {
+                continue;
+            }
+            last_token = Some(line.token);
+            if let Some(url) = self.link_for_token(line.token).and_then(|l| l.url.clone()) {
+                targets.push((row, url));
+            }
+        }
+        if targets.is_empty() {
+            return false;
+        }
+
+        let hints = hint_labels(targets.len())
+            .into_iter()
+            .zip(targets)
+            .map(|(label, (row, url))| Hint { label, row, url })
+            .collect();
+        self.hint_mode = Some(HintMode {
+            hints,
+            typed: String::new(),
+            new_tab,
+        });
+        true
+    }
+
+    pub fn hints(&self) -> &[Hint] {
+        match &self.hint_mode {
+            Some(mode) => &mode.hints,
+            None => &[],
+        }
+    }
+
+    fn on_hint_event(&mut self, event: Event) -> EventResult {
+        let mode = match &mut self.hint_mode {
+            Some(mode) => mode,
+            None => return EventResult::Ignored,
+        };
+        match event {
+            Event::Char(c) if c.is_ascii_digit() => mode.typed.push(c),
+            Event::Key(Key::Backspace) => {
+                mode.typed.pop();
+                return EventResult::Consumed(None);
+            },
+            // Anything else leaves hint mode.
+            _ => {
+                self.hint_mode = None;
+                return EventResult::Consumed(None);
+            },
+        }
+
+        if !mode.hints.iter().any(|hint| hint.label.starts_with(&mode.typed)) {
+            self.hint_mode = None;
+            return EventResult::Consumed(None);
+        }
+        let picked = mode.hints.iter().find(|hint| hint.label == mode.typed);
+        let (url, new_tab) = match picked {
+            Some(hint) => (hint.url.clone(), mode.new_tab),
+            None => return EventResult::Consumed(None),
+        };
+        self.hint_mode = None;
+        match self.on_follow.clone() {
+            Some(on_follow) => EventResult::with_cb(move |s| on_follow(s, &url, new_tab)),
+            None => EventResult::Consumed(None),
+        }
+    }
+
+    // Highlights every match of query and makes the first one at or below
+    // the top of the view current. Returns how many matches there are.
+    pub fn search(&mut self, query: &str, options: &SearchSettings)
+        -> Result<usize, SearchError> {
+        let matches = match find_matches(&self.lines, query, options) {
+            Ok(matches) => matches,
+            Err(error) => {
+                self.search = None;
+                return Err(error);
+            },
+        };
+        let (top, _) = self.visible.get();
+        let current = match matches.iter().position(|m| m.row() >= top) {
+            Some(i) => Some(i),
+            None if matches.is_empty() => None,
+            None => Some(0),
+        };
+        let count = matches.len();
+        self.search = Some(Search {
+            query: query.to_owned(),
+            options: options.clone(),
+            matches,
+            current,
+        });
+        Ok(count)
+    }
+
+    pub fn clear_search(&mut self) {
+        self.search = None;
+    }
+
+    // Moves to the next match, or the previous one when forward is false,
+    // wrapping around at either end of the page.
+    pub fn next_match(&mut self, forward: bool) {
+        if let Some(search) = &mut self.search {
+            let count = search.matches.len();
+            if count == 0 {
+                return;
+            }
+            search.current = Some(match search.current {
+                Some(i) if forward => (i + 1) % count,
+                Some(i) => (i + count - 1) % count,
+                None => 0,
+            });
+        }
+    }
+
+    // The current match as its 1-based position and the number of matches.
+    pub fn match_count(&self) -> Option<(usize, usize)> {
+        let search = self.search.as_ref()?;
+        let current = search.current.map(|i| i + 1).unwrap_or(0);
+        Some((current, search.matches.len()))
+    }
+
+    // Where the current match starts, in cells from the top left of the page.
+    pub fn current_match_position(&self) -> Option<Vec2> {
+        let search = self.search.as_ref()?;
+        let found = &search.matches[search.current?].spans[0];
+        let x = text_width(&self.lines[found.row].text[..found.start]);
+        Some(Vec2::new(x, found.row))
+    }
+
+    // Moves the cursor to the next link that leads somewhere, or the
+    // previous one when forward is false, and returns its first row. With
+    // no link selected, or the selected one scrolled out of view, the cursor
+    // starts from the edge of the view it's moving away from.
+    pub fn select_link(&mut self, forward: bool) -> Option<usize> {
+        let (top, bottom) = self.visible.get();
+        let rows: Vec<(usize, usize)> = self.links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.url.is_some())
+            .filter_map(|(i, link)| self.row_for_token(link.token).map(|row| (i, row)))
+            .collect();
+        let current = self.selected_link
+            .and_then(|selected| rows.iter().position(|(i, _)| *i == selected))
+            .filter(|&position| (top..bottom).contains(&rows[position].1));
+        let next = match current {
+            Some(position) if forward => rows.get(position + 1),
+            Some(position) => position.checked_sub(1).and_then(|p| rows.get(p)),
+            None if forward => rows.iter().find(|(_, row)| *row >= top),
+            None => rows.iter().rev().find(|(_, row)| *row < bottom.max(1)),
+        };
+        let (i, row) = *next?;
+        self.selected_link = Some(i);
+        Some(row)
+    }
+
+    pub fn selected_url(&self) -> Option<&Url> {
+        self.links.get(self.selected_link?)?.url.as_ref()
+    }
+
+    pub fn outline(&self) -> Vec<OutlineEntry> {
+        outline(&self.chain)
+    }
+
+    // The first row of a token, once the page has been laid out.
+    pub fn row_for_token(&self, token: usize) -> Option<usize> {
+        self.lines.iter().position(|line| line.token == token)
+    }
+
+    // The heading whose section is at the top of the view.
+    pub fn current_heading(&self) -> Option<OutlineEntry> {
+        let (top, _) = self.visible.get();
+        self.outline()
+            .into_iter()
+            .take_while(|entry| self.row_for_token(entry.token).is_some_and(|row| row <= top))
+            .last()
+    }
+
+    // The row of the next heading below the top of the view, or of the
+    // closest one above it when forward is false.
+    pub fn heading_row(&self, forward: bool) -> Option<usize> {
+        let (top, _) = self.visible.get();
+        let mut rows = self.outline()
+            .into_iter()
+            .filter_map(|entry| self.row_for_token(entry.token));
+        if forward {
+            rows.find(|row| *row > top)
+        } else {
+            rows.filter(|row| *row < top).last()
+        }
+    }
+
+    fn relayout(&mut self, width: usize) {
+        let options = LayoutOptions {
+            width,
+            bidi: self.settings.bidi,
+            direction_hint: self.direction_hint,
+            highlight: self.settings.syntax_highlighting,
+            markers: self.links
+                .iter()
+                .map(|link| (link.token, link.kind.marker()))
+                .collect(),
+        };
+        self.lines = layout_gemtext(&self.chain, &options);
+        self.image_rows = self.image
+            .as_ref()
+            .map(|image| image_rows(image, width))
+            .unwrap_or_default();
+        self.layout_width = Some(width);
+
+        // Rows move around when the page reflows, so search again.
+        if let Some(search) = &mut self.search {
+            search.matches = find_matches(&self.lines, &search.query, &search.options)
+                .unwrap_or_default();
+            search.current = search.current
+                .map(|i| i.min(search.matches.len().saturating_sub(1)))
+                .filter(|_| !search.matches.is_empty());
+        }
+    }
+
+    fn image_color(&self, rgb: [u8; 3]) -> ColorType {
+        let [r, g, b] = rgb;
+        ColorType::Color(match self.color_depth {
+            ColorDepth::TrueColor => Color::Rgb(r, g, b),
+            ColorDepth::Palette256 => Color::from_256colors(to_256_colors(rgb)),
+        })
+    }
+
+    // Draws the image rows that are in view, below the lines of text.
+    // Transparent halves let the page background through.
+    fn draw_image(&self, printer: &Printer, start: usize, end: usize) {
+        let top = self.lines.len();
+        for (y, row) in self.image_rows.iter().enumerate() {
+            let y = top + y;
+            if y < start || y >= end {
+                continue;
+            }
+            for (x, cell) in row.iter().enumerate() {
+                let (block, fg, bg) = match (cell.top, cell.bottom) {
+                    (Some(top), bottom) => ("▀", top, bottom),
+                    (None, Some(bottom)) => ("▄", bottom, None),
+                    (None, None) => continue,
+                };
+                let bg = bg.map(|bg| self.image_color(bg)).unwrap_or(ColorType::InheritParent);
+                let style = ColorStyle::new(self.image_color(fg), bg);
+                printer.with_color(style, |printer| printer.print((x, y), block));
+            }
+        }
+    }
+}
+
+impl View for PageView {
+    fn draw(&self, printer: &Printer) {
+        let start = printer.content_offset.y;
+        let end = start + printer.output_size.y;
+        self.visible.set((start, end));
+        for (y, line) in self.lines.iter().enumerate().take(end).skip(start) {
+            let style = element_style(&self.theme, self.element_for_line(line));
+            // Print the text between highlighted spans in the token's own
+            // style and the spans themselves in their highlight colors.
+            let mut x = 0;
+            let mut printed = 0;
+            for span in &line.spans {
+                let before = &line.text[printed..span.start];
+                printer.with_style(style, |printer| {
+                    printer.print((x, y), before);
+                });
+                x += text_width(before);
+                let highlighted = &line.text[span.start..span.end];
+                let element = Element::for_highlight(span.kind);
+                printer.with_style(element_style(&self.theme, element), |printer| {
+                    printer.print((x, y), highlighted);
+                });
+                x += text_width(highlighted);
+                printed = span.end;
+            }
+            printer.with_style(style, |printer| {
+                printer.print((x, y), &line.text[printed..]);
+            });
+        }
+
+        self.draw_image(printer, start, end);
+
+        let selected_token = self.selected_link.map(|i| self.links[i].token);
+        if let Some(token) = selected_token {
+            let style = element_style(&self.theme, Element::Highlight);
+            for (y, line) in self.lines.iter().enumerate().take(end).skip(start) {
+                if line.token == token {
+                    printer.with_style(style, |printer| printer.print((0, y), &line.text));
+                }
+            }
+        }
+
+        if let Some(search) = &self.search {
+            // A match that starts above the view can still end in it.
+            let first = search.matches.partition_point(|m| m.last_row() < start);
+            for (i, found) in search.matches.iter().enumerate().skip(first) {
+                if found.row() >= end {
+                    break;
+                }
+                let element = if search.current == Some(i) {
+                    Element::CurrentMatch
+                } else {
+                    Element::SearchMatch
+                };
+                for span in found.spans.iter().filter(|span| (start..end).contains(&span.row)) {
+                    let text = &self.lines[span.row].text;
+                    let x = text_width(&text[..span.start]);
+                    printer.with_style(element_style(&self.theme, element), |printer| {
+                        printer.print((x, span.row), &text[span.start..span.end]);
+                    });
+                }
+            }
+        }
+
+        if let Some(mode) = &self.hint_mode {
+            let style = element_style(&self.theme, Element::Hint);
+            for hint in &mode.hints {
+                // Labels that no longer match what's been typed are hidden.
+                if hint.label.starts_with(&mode.typed) {
+                    printer.with_style(style, |printer| {
+                        printer.print((0, hint.row), &hint.label);
+                    });
+                }
+            }
+        }
+    }
+
+    fn required_size(&mut self, constraint: Vec2) -> Vec2 {
+        // Wrap at the configured column, or to the view when that's 0.
+        let width = if self.settings.wrap_width == 0 {
+            constraint.x
+        } else {
+            self.settings.wrap_width.min(constraint.x)
+        };
+        if self.layout_width != Some(width) {
+            self.relayout(width);
+        }
+
+        // Preformatted lines can be wider than the page, in which case the
+        // surrounding ScrollView scrolls horizontally.
+        let longest = self.lines
+            .iter()
+            .map(|line| text_width(&line.text))
+            .chain(self.image_rows.iter().map(|row| row.len()))
+            .max()
+            .unwrap_or(0);
+        Vec2::new(width.max(longest), self.lines.len() + self.image_rows.len())
+    }
+
+    fn take_focus(&mut self, _: cursive::direction::Direction) -> bool {
+        true
+    }
+
+    fn on_event(&mut self, event: Event) -> EventResult {
+        if self.hint_mode.is_some() {
+            return self.on_hint_event(event);
+        }
+        match event {
+            Event::Mouse { offset, position, event } => {
+                // Any release ends a press, wherever it happens.
+                let pressed = match event {
+                    MouseEvent::Release(_) => self.pressed_link.take(),
+                    _ => None,
+                };
+                match position.checked_sub(offset) {
+                    Some(position) => self.on_mouse_event(position, event, pressed),
+                    None => EventResult::Ignored,
+                }
+            },
+            _ => EventResult::Ignored,
+        }
+    }
+
+    // Keeps whatever was last drawn in view, so consuming an event doesn't
+    // make the surrounding ScrollView jump.
+    fn important_area(&self, size: Vec2) -> Rect {
+        let (start, end) = self.visible.get();
+        let end = end.min(size.y).max(start + 1);
+        Rect::from_corners((0, start), (size.x.max(1) - 1, end - 1))
+    }
+
+    fn layout(&mut self, _: Vec2) {
+        self.dirty = false;
+    }
+
+    fn needs_relayout(&self) -> bool {
+        self.dirty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gemtext::parse_gemtext;
+    use crate::links::page_links;
+
+    #[test]
+    fn hint_labels_are_the_same_length() {
+        assert_eq!(hint_labels(3), vec!["1", "2", "3"]);
+        let labels = hint_labels(12);
+        assert_eq!(labels[0], "01");
+        assert_eq!(labels[11], "12");
+    }
+
+    #[test]
+    fn hints_cover_visible_links_only() {
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let chain = parse_gemtext("=> a.gmi A\nText\n=> b.gmi B\n=> c.gmi C\n");
+        let links = page_links(&chain, Some(&base), |_| false);
+        let mut page = PageView::new(&DisplaySettings::default(), Theme::default());
+        page.set_content(chain, links);
+        page.required_size(Vec2::new(40, 10));
+        page.visible.set((1, 3));
+
+        assert!(page.start_hints(false));
+        let hints = page.hints();
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].row, 2);
+        assert_eq!(hints[0].url.as_str(), "gemini://example.org/b.gmi");
+
+        page.on_event(Event::Key(Key::Esc));
+        assert!(page.hints().is_empty());
+    }
+
+    #[test]
+    fn headings_are_found_around_the_view() {
+        let chain = parse_gemtext("# One\ntext\ntext\n## Two\ntext\n## Three\n");
+        let mut page = PageView::new(&DisplaySettings::default(), Theme::default());
+        page.set_content(chain, Vec::new());
+        page.required_size(Vec2::new(40, 10));
+        page.visible.set((3, 5));
+
+        assert_eq!(page.heading_row(true), Some(5));
+        assert_eq!(page.heading_row(false), Some(0));
+        assert_eq!(page.current_heading().unwrap().title, "Two");
+    }
+
+    #[test]
+    fn search_moves_between_matches() {
+        let chain = parse_gemtext("one cat\ntwo\nthree cat\n");
+        let mut page = PageView::new(&DisplaySettings::default(), Theme::default());
+        page.set_content(chain, Vec::new());
+        page.required_size(Vec2::new(40, 10));
+
+        assert_eq!(page.search("cat", &SearchSettings::default()).unwrap(), 2);
+        assert_eq!(page.current_match_position(), Some(Vec2::new(4, 0)));
+        page.next_match(true);
+        assert_eq!(page.match_count(), Some((2, 2)));
+        assert_eq!(page.current_match_position(), Some(Vec2::new(6, 2)));
+        page.next_match(true);
+        assert_eq!(page.match_count(), Some((1, 2)));
+        page.next_match(false);
+        assert_eq!(page.match_count(), Some((2, 2)));
+    }
+
+    #[test]
+    fn updating_content_keeps_search_and_selection() {
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let chain = parse_gemtext("a.gmi 10 KB\n=> a.gmi Open\n");
+        let links = page_links(&chain, Some(&base), |_| false);
+        let mut page = PageView::new(&DisplaySettings::default(), Theme::default());
+        page.set_content(chain, links);
+        page.required_size(Vec2::new(40, 10));
+        page.visible.set((0, 2));
+        page.search("KB", &SearchSettings::default()).unwrap();
+        page.select_link(true);
+
+        let chain = parse_gemtext("a.gmi 20 KB\n=> a.gmi Open\n");
+        let links = page_links(&chain, Some(&base), |_| false);
+        page.update_content(chain, links);
+        page.required_size(Vec2::new(40, 10));
+        assert_eq!(page.match_count(), Some((1, 1)));
+        assert_eq!(page.selected_url().unwrap().as_str(), "gemini://example.org/a.gmi");
+    }
+
+    #[test]
+    fn link_cursor_moves_between_links() {
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let chain = parse_gemtext("=> a.gmi A\nText\n=> b.gmi B\n=> c.gmi C\n");
+        let links = page_links(&chain, Some(&base), |_| false);
+        let mut page = PageView::new(&DisplaySettings::default(), Theme::default());
+        page.set_content(chain, links);
+        page.required_size(Vec2::new(40, 10));
+        page.visible.set((1, 4));
+
+        assert_eq!(page.selected_url(), None);
+        assert_eq!(page.select_link(true), Some(2));
+        assert_eq!(page.selected_url().unwrap().as_str(), "gemini://example.org/b.gmi");
+        assert_eq!(page.select_link(true), Some(3));
+        assert_eq!(page.select_link(true), None);
+        assert_eq!(page.select_link(false), Some(2));
+        page.visible.set((0, 4));
+        assert_eq!(page.select_link(false), Some(0));
+    }
+
+    #[test]
+    fn clicks_land_on_link_text() {
+        let base = Url::parse("gemini://example.org/").unwrap();
+        let chain = parse_gemtext("=> a.gmi A\nText\n=> b.gmi Bee\n");
+        let links = page_links(&chain, Some(&base), |_| false);
+        let mut page = PageView::new(&DisplaySettings::default(), Theme::default());
+        page.set_content(chain, links);
+        page.required_size(Vec2::new(40, 10));
+
+        assert_eq!(page.link_at(Vec2::new(0, 0)), Some(0));
+        assert_eq!(page.link_at(Vec2::new(0, 1)), None);
+        assert_eq!(page.link_at(Vec2::new(2, 2)), Some(1));
+        assert_eq!(page.link_at(Vec2::new(30, 2)), None);
+
+        let press = Event::Mouse {
+            offset: Vec2::new(1, 1),
+            position: Vec2::new(3, 3),
+            event: MouseEvent::Press(MouseButton::Left),
+        };
+        let release = |x| Event::Mouse {
+            offset: Vec2::new(1, 1),
+            position: Vec2::new(x, 3),
+            event: MouseEvent::Release(MouseButton::Left),
+        };
+        assert!(!page.on_event(release(3)).is_consumed());
+        assert!(page.on_event(press.clone()).is_consumed());
+        assert_eq!(page.selected_url().unwrap().as_str(), "gemini://example.org/b.gmi");
+        assert!(page.on_event(release(3)).is_consumed());
+
+        // Letting go anywhere else ends the press, so coming back to the
+        // link later doesn't follow it.
+        assert!(page.on_event(press).is_consumed());
+        assert!(!page.on_event(release(30)).is_consumed());
+        assert!(!page.on_event(release(3)).is_consumed());
+    }
+}
@@ -0,0 +1,83 @@
+use cursive::theme::{
+    BaseColor,
+    BorderStyle,
+    Color,
+    ColorStyle,
+    ColorType,
+    Effect,
+    Palette,
+    PaletteColor::*,
+    Style,
+};
+
+use crate::theme::{Element, ElementStyle, Theme, ThemeColor, ThemeEffect};
+
+pub fn to_color(color: ThemeColor) -> Color {
+    match color {
+        ThemeColor::Default => Color::TerminalDefault,
+        ThemeColor::Dark(n) => Color::Dark(BaseColor::from(n)),
+        ThemeColor::Light(n) => Color::Light(BaseColor::from(n)),
+        ThemeColor::Rgb(r, g, b) => Color::Rgb(r, g, b),
+    }
+}
+
+fn to_color_type(color: Option<ThemeColor>) -> ColorType {
+    match color {
+        Some(color) => ColorType::Color(to_color(color)),
+        None => ColorType::InheritParent,
+    }
+}
+
+pub fn to_style(style: &ElementStyle) -> Style {
+    let mut cursive_style = Style::from(ColorStyle::new(
+        to_color_type(style.fg),
+        to_color_type(style.bg),
+    ));
+    for effect in &style.effects {
+        let effect = match effect {
+            ThemeEffect::Bold => Effect::Bold,
+            ThemeEffect::Italic => Effect::Italic,
+            ThemeEffect::Underline => Effect::Underline,
+            ThemeEffect::Reverse => Effect::Reverse,
+            ThemeEffect::Strikethrough => Effect::Strikethrough,
+        };
+        cursive_style.effects.insert(effect);
+    }
+    cursive_style
+}
+
+pub fn element_style(theme: &Theme, element: Element) -> Style {
+    to_style(&theme.style(element))
+}
+
+// Builds the cursive theme for the interface around the page from the UI
+// elements of a theme.
+pub fn to_cursive_theme(theme: &Theme) -> cursive::theme::Theme {
+    let color = |c: Option<ThemeColor>| to_color(c.unwrap_or(ThemeColor::Default));
+    let view = theme.style(Element::View);
+    let background = theme.style(Element::Background);
+    let secondary = theme.style(Element::Secondary);
+    let title = theme.style(Element::Title);
+    let highlight = theme.style(Element::Highlight);
+
+    let mut palette = Palette::default();
+    let colors = vec![
+        (Background, color(background.bg)),
+        (Shadow, color(background.bg)),
+        (View, color(view.bg)),
+        (Primary, color(view.fg)),
+        (Secondary, color(secondary.fg)),
+        (Tertiary, color(secondary.fg)),
+        (TitlePrimary, color(title.fg)),
+        (TitleSecondary, color(title.fg)),
+        (Highlight, color(highlight.bg)),
+        (HighlightInactive, color(highlight.bg)),
+        (HighlightText, color(highlight.fg)),
+    ];
+    palette.extend(colors);
+    cursive::theme::Theme {
+        shadow: false,
+        borders: BorderStyle::Simple,
+        palette,
+    }
+}
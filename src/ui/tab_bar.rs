@@ -0,0 +1,116 @@
+use std::cell::Cell;
+
+use cursive::event::{Event, EventResult, MouseButton, MouseEvent};
+use cursive::theme::ColorStyle;
+use cursive::{Printer, Vec2, View};
+
+use crate::keymap::Action;
+use crate::layout::text_width;
+use crate::ui::browser::Browser;
+use crate::ui::tui::{run_action, show_tab};
+
+// Longest tab title shown in the tab bar, in characters.
+const MAX_TAB_TITLE: usize = 24;
+
+// The row of tabs above the address bar, with the current one highlighted.
+// It never takes focus, so clicks are handed to it by the view around the
+// page, and it works out from where it was last drawn which are its own.
+pub struct TabBar {
+    // Each tab's entry, as " 1 title ".
+    entries: Vec<String>,
+    current: usize,
+    // Where the bar was last drawn on the screen.
+    origin: Cell<Vec2>,
+}
+
+impl TabBar {
+    pub fn new() -> Self {
+        TabBar {
+            entries: Vec::new(),
+            current: 0,
+            origin: Cell::new(Vec2::zero()),
+        }
+    }
+
+    // Shows a tab for each of titles, highlighting the one at current.
+    pub fn set_tabs(&mut self, titles: &[String], current: usize) {
+        self.entries = titles
+            .iter()
+            .enumerate()
+            .map(|(i, title)| {
+                let title: String = title.chars().take(MAX_TAB_TITLE).collect();
+                format!(" {} {} ", i + 1, title)
+            })
+            .collect();
+        self.current = current;
+    }
+
+    // The tab whose entry is drawn at position on the screen.
+    fn tab_at(&self, position: Vec2) -> Option<usize> {
+        let position = position.checked_sub(self.origin.get())?;
+        if position.y != 0 {
+            return None;
+        }
+        let mut x = position.x;
+        for (i, entry) in self.entries.iter().enumerate() {
+            let width = text_width(entry);
+            if x < width {
+                return Some(i);
+            }
+            x -= width;
+        }
+        None
+    }
+
+    // Switches to a tab when it's clicked, or closes it on a middle click.
+    // Clicks anywhere else are left alone.
+    pub fn on_mouse(&self, event: &Event) -> Option<EventResult> {
+        let (position, button) = match *event {
+            Event::Mouse { position, event: MouseEvent::Press(button), .. } => (position, button),
+            _ => return None,
+        };
+        let tab = self.tab_at(position)?;
+        Some(EventResult::with_cb(move |s| {
+            if let Some(browser) = s.user_data::<Browser>() {
+                browser.current_tab = tab;
+            }
+            if button == MouseButton::Middle {
+                run_action(s, Action::CloseTab);
+            } else {
+                show_tab(s);
+            }
+        }))
+    }
+}
+
+impl Default for TabBar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl View for TabBar {
+    fn draw(&self, printer: &Printer) {
+        self.origin.set(printer.offset);
+        if self.entries.is_empty() {
+            printer.print((0, 0), "New tab");
+            return;
+        }
+        let mut x = 0;
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i == self.current {
+                printer.with_color(ColorStyle::highlight(), |printer| printer.print((x, 0), entry));
+            } else {
+                printer.print((x, 0), entry);
+            }
+            x += text_width(entry);
+        }
+    }
+
+    fn layout(&mut self, _: Vec2) {}
+
+    fn required_size(&mut self, _: Vec2) -> Vec2 {
+        let width = self.entries.iter().map(|entry| text_width(entry)).sum::<usize>();
+        Vec2::new(width.max(text_width("New tab")), 1)
+    }
+}
@@ -1,113 +1,1035 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, Instant};
+
 use cursive::Cursive;
-use cursive::event;
-use cursive::theme::{
-    BorderStyle,
-    BaseColor::*,
-    Color::*,
-    Palette,
-    PaletteColor::*,
-    // Style,
-    Theme,
-};
-use cursive::utils::markup::StyledString;
-use cursive::view::{Nameable, Margins, SizeConstraint};
+use cursive::event::{self, Event, EventResult, EventTrigger, Key};
+use cursive::view::{Finder, Nameable, Margins, Resizable, Scrollable, SizeConstraint};
 use cursive::views::{
     Dialog,
     DummyView,
     EditView,
+    HideableView,
     LinearLayout,
+    NamedView,
     OnEventView,
     PaddedView,
     Panel,
     ResizedView,
     ScrollView,
+    SelectView,
     TextView,
 };
+use cursive::Vec2;
+use image::RgbaImage;
 use url::Url;
 
-use crate::transaction::visit::visit;
-use crate::gemtext::{GemtextToken, parse_gemtext};
+use crate::address::parse_address;
+use crate::bookmarks::Bookmarks;
+use crate::clipboard::copy_to_clipboard;
+use crate::keymap::{Action, KeyPress, Keymap, NamedKey};
+use crate::downloads::format_size;
+use crate::transaction::response::create_fake_response;
+use crate::transaction::tofu::{host_key, KnownHosts, TrustState};
+use crate::transaction::visit::{open, OpenError, ResponseStream, Transaction};
+use crate::gemtext::{parse_body, parse_gemtext, GemtextToken};
+use crate::history::History;
+use crate::image_art::{decode_image, is_image};
+use crate::layout::{direction_for_lang, Direction};
+use crate::links::{page_links, PageLink};
+use crate::session::Session;
+use crate::settings::{load_theme, SearchSettings, Settings, ThemeSettings};
+use crate::ui::address_bar::{address_bar, edit_address, show_address, AddressBar};
+use crate::ui::browser::{Browser, PageInfo, Source};
+use crate::ui::command_line::{command_bar, open_command_line};
+use crate::ui::downloads::{
+    downloads_action,
+    save_as_dialog,
+    save_source_dialog,
+    DOWNLOADS_URL,
+};
+use crate::ui::handlers::open_with_dialog;
+use crate::theme::{Theme, BUILTIN_THEMES};
+use crate::ui::page_view::PageView;
+use crate::ui::styles::to_cursive_theme;
+use crate::ui::tab_bar::TabBar;
+
+type PageScrollView = ScrollView<NamedView<PageView>>;
 
-pub fn init_ui() -> Cursive {
-    let mut app = Cursive::new();
+// How often the session is saved while armstrong runs.
+const SESSION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Rows kept between a search match and the edge of the page when scrolling
+// to it.
+const SEARCH_CONTEXT_ROWS: usize = 3;
 
-    let mut palette = Palette::default();
-    let colors = vec![
-        (Background, Rgb(0, 0, 0)),
-        (View, Rgb(0, 0, 0)),
-        (Primary, Light(White)),
-        (Secondary, Light(White)),
-        (TitlePrimary, Light(White)),
-    ];
-    palette.extend(colors);
-    let theme = Theme {
-        shadow: false,
-        borders: BorderStyle::Simple,
-        palette: palette,
-    };
-    app.set_theme(theme);
+pub fn init_ui(settings: Settings, history: History, bookmarks: Bookmarks,
+               known_hosts: KnownHosts, theme: Theme, keymap: Keymap) -> Cursive {
+    let mut app = Cursive::new();
+    app.set_theme(to_cursive_theme(&theme));
 
     // Create default layout
+    let mut page = PageView::new(&settings.display, theme);
+    page.set_on_follow(|s, url, new_tab| {
+        if new_tab {
+            open_in_new_tab(s, url.clone());
+        } else {
+            open_url(s, url.clone());
+        }
+    });
+    page.set_on_menu(|s, url, text| link_menu(s, url.clone(), text));
     let page_view = PaddedView::new(
         Margins::lrtb(4, 4, 1, 1),
-        ScrollView::new(
-            ResizedView::new(
-                SizeConstraint::Fixed(100),
-                SizeConstraint::Full,
-                TextView::new("New tab")
-                .with_name("page")
-            )
+        ResizedView::new(
+            SizeConstraint::Full,
+            SizeConstraint::Full,
+            ScrollView::new(page.with_name("page"))
+            .scroll_x(true)
+            .with_name("page_scroll")
         )
     );
 
     let ui_view = LinearLayout::vertical()
         .child(PaddedView::new(
                 Margins::lr(1, 0),
-                LinearLayout::horizontal()
-                .child(TextView::new("New tab"))
-                .with_name("tab_bar")
+                TabBar::new().with_name("tab_bar")
         ))
-        .child(Panel::new(page_view));
+        .child(PaddedView::new(Margins::lr(1, 1), address_bar().with_name("address_bar")))
+        .child(Panel::new(page_view))
+        .child(status_bar())
+        .child(search_bar())
+        .child(command_bar());
 
+    // Keys the page doesn't use itself go through the keymap. The bars above
+    // the page never have focus, so clicks on them are caught on the way in.
     let event_view = OnEventView::new(ui_view)
-        .on_event('q', |s| quit_dialog(s))
-        .on_event(event::Key::Esc, |s| quit_dialog(s))
-        .on_event(event::Event::Char('g'), |s: &mut Cursive| goto_dialog(s));
+        .on_pre_event_inner(EventTrigger::mouse(), on_bar_click)
+        .on_event_inner(EventTrigger::any(), |_, event| {
+            let key = key_press(event)?;
+            Some(EventResult::with_cb(move |s| on_key(s, key)))
+        });
 
-    app.add_layer(event_view);
-    goto_dialog(&mut app);
+    let conflicts = keymap.conflicts();
+    app.add_fullscreen_layer(event_view);
+    app.set_user_data(Browser::new(settings, history, bookmarks, known_hosts, keymap));
+    if !conflicts.is_empty() {
+        app.add_layer(Dialog::info(conflicts.join("\n")).title("Conflicting key bindings"));
+    }
     app
 }
 
-fn update_tab(app: &mut Cursive, s: &str) {
-    let url = Url::parse(&s).unwrap();
-    let response = visit(&url);
-    let chain = parse_gemtext(&response.body);
-    let ss = styled_string_from_token_chain(&chain);
+// Opens the tabs of the last session if there are any to restore, and then
+// urls in tabs of their own. With neither, the home page is opened, or the
+// address bar if there's no home page set.
+pub fn open_start_page(app: &mut Cursive, session: Option<Session>, urls: Vec<Url>) {
+    let (restored, home) = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let restored = session.is_some_and(|session| browser.restore(&session));
+            let general = &browser.settings.general;
+            let home = if general.home.trim().is_empty() {
+                None
+            } else {
+                Some(parse_address(&general.home, &general.search_url))
+            };
+            (restored, home)
+        },
+        None => return,
+    };
+    if !urls.is_empty() {
+        for (i, url) in urls.into_iter().enumerate() {
+            if restored || i > 0 {
+                open_in_new_tab(app, url);
+            } else {
+                open_url(app, url);
+            }
+        }
+        return;
+    }
+    match (restored, home) {
+        (true, _) => show_tab(app),
+        (false, Some(Ok(url))) => open_url(app, url),
+        (false, Some(Err(error))) => {
+            edit_address(app);
+            app.add_layer(Dialog::info(format!("Couldn't open the home page: {}", error)));
+        },
+        (false, None) => edit_address(app),
+    }
+}
 
-    let mut page = app.find_name::<TextView>("page").unwrap();
-    page.set_content(ss);
-    app.pop_layer();
+// Saves the open tabs to path, if sessions are to be restored.
+pub fn save_session(app: &mut Cursive, path: &Path) -> io::Result<()> {
+    remember_scroll(app);
+    match app.user_data::<Browser>() {
+        Some(browser) if browser.settings.general.restore_session => browser.session().save(path),
+        _ => Ok(()),
+    }
 }
 
-fn goto_dialog(app: &mut Cursive) {
-    let layout = LinearLayout::vertical()
-        .child(DummyView)
-        .child(TextView::new("Example: gemini.circumlunar.space"))
-        .child(EditView::new()
-            .on_submit(update_tab)
-            .with_name("urlbox"));
+// Saves the session every so often while armstrong runs, so a crash loses
+// little of it.
+pub fn save_session_periodically(app: &mut Cursive, path: PathBuf) {
+    let sink = app.cb_sink().clone();
+    thread::spawn(move || loop {
+        thread::sleep(SESSION_SAVE_INTERVAL);
+        let path = path.clone();
+        let save = Box::new(move |s: &mut Cursive| {
+            // A failed save is tried again next time, and on quitting.
+            let _ = save_session(s, &path);
+        });
+        if sink.send(save).is_err() {
+            break;
+        }
+    });
+}
+
+// Keeps the row the page is scrolled to in the current tab, so it can be
+// put back when the tab is shown again. A restored tab that hasn't been
+// fetched yet isn't the one in the page view, so it keeps its own.
+fn remember_scroll(app: &mut Cursive) {
+    let offset = app.call_on_name("page_scroll", |scroll: &mut PageScrollView| {
+        scroll.content_viewport().top_left()
+    });
+    if let (Some(browser), Some(offset)) = (app.user_data::<Browser>(), offset) {
+        let tab = browser.tab_mut();
+        if !tab.restored {
+            tab.scroll = offset.y;
+        }
+    }
+}
+
+// Hands clicks to the tab bar and the address bar, which never have focus
+// and so wouldn't see them otherwise.
+fn on_bar_click(view: &mut LinearLayout, event: &Event) -> Option<EventResult> {
+    view.call_on_name("tab_bar", |bar: &mut TabBar| bar.on_mouse(event))
+        .flatten()
+        .or_else(|| view.call_on_name("address_bar", |bar: &mut AddressBar| bar.on_mouse(event)).flatten())
+}
+
+fn named_key(key: Key) -> Option<NamedKey> {
+    let named = match key {
+        Key::Esc => NamedKey::Esc,
+        Key::Enter => NamedKey::Enter,
+        Key::Tab => NamedKey::Tab,
+        Key::Backspace => NamedKey::Backspace,
+        Key::Del => NamedKey::Delete,
+        Key::Ins => NamedKey::Insert,
+        Key::Up => NamedKey::Up,
+        Key::Down => NamedKey::Down,
+        Key::Left => NamedKey::Left,
+        Key::Right => NamedKey::Right,
+        Key::Home => NamedKey::Home,
+        Key::End => NamedKey::End,
+        Key::PageUp => NamedKey::PageUp,
+        Key::PageDown => NamedKey::PageDown,
+        _ => return None,
+    };
+    Some(named)
+}
+
+fn key_press(event: &Event) -> Option<KeyPress> {
+    match event {
+        Event::Char(c) => Some(KeyPress::Char(*c)),
+        Event::CtrlChar(c) => Some(KeyPress::Ctrl(*c)),
+        Event::AltChar(c) => Some(KeyPress::Alt(*c)),
+        Event::Key(key) => named_key(*key).map(KeyPress::Key),
+        Event::Shift(key) => named_key(*key).map(KeyPress::Shift),
+        _ => None,
+    }
+}
+
+// Keeps key presses from reaching the keymap while typing into a bar.
+pub fn swallow_keys(event: &Event) -> Option<EventResult> {
+    key_press(event).map(|_| EventResult::Consumed(None))
+}
+
+fn on_key(app: &mut Cursive, key: KeyPress) {
+    let action = match app.user_data::<Browser>() {
+        Some(browser) => browser.keymap.press(&mut browser.pending_keys, key),
+        None => None,
+    };
+    if let Some(action) = action {
+        run_action(app, action);
+    }
+}
+
+pub fn run_action(app: &mut Cursive, action: Action) {
+    match action {
+        Action::Goto => edit_address(app),
+        Action::Back => go_back(app),
+        Action::Forward => go_forward(app),
+        Action::Reload => reload(app),
+        Action::NewTab => {
+            remember_scroll(app);
+            if let Some(browser) = app.user_data::<Browser>() {
+                browser.open_tab();
+            }
+            show_tab(app);
+            edit_address(app);
+        },
+        Action::CloseTab => {
+            if let Some(browser) = app.user_data::<Browser>() {
+                browser.close_tab();
+            }
+            show_tab(app);
+        },
+        Action::NextTab => cycle_tab(app, 1),
+        Action::PrevTab => cycle_tab(app, -1),
+        Action::FollowHint => start_hints(app, false),
+        Action::FollowHintNewTab => start_hints(app, true),
+        Action::NextLink => select_link(app, true),
+        Action::PrevLink => select_link(app, false),
+        Action::FollowLink => follow_link(app),
+        Action::Search => open_search(app),
+        Action::NextMatch => jump_to_match(app, true),
+        Action::PrevMatch => jump_to_match(app, false),
+        Action::Outline => outline_dialog(app),
+        Action::NextHeading => jump_to_heading(app, true),
+        Action::PrevHeading => jump_to_heading(app, false),
+        Action::Bookmark => bookmark_page(app),
+        Action::SaveAs => {
+            let url = app.user_data::<Browser>().and_then(|browser| browser.tab().url.clone());
+            if let Some(url) = url {
+                save_as_dialog(app, url);
+            }
+        },
+        Action::ViewSource => {
+            if let Some(browser) = app.user_data::<Browser>() {
+                let tab = browser.tab_mut();
+                tab.view_source = !tab.view_source && tab.url.is_some();
+            }
+            show_tab(app);
+        },
+        Action::CopySource => {
+            let source = app.user_data::<Browser>()
+                .map(|browser| String::from_utf8_lossy(&browser.tab().source.body).into_owned());
+            if let Some(Err(error)) = source.map(|source| copy_to_clipboard(&source)) {
+                app.add_layer(Dialog::info(error));
+            }
+        },
+        Action::SaveSource => save_source_dialog(app),
+        Action::ScrollDown => scroll_by(app, 0, 1),
+        Action::ScrollUp => scroll_by(app, 0, -1),
+        Action::ScrollLeft => scroll_by(app, -1, 0),
+        Action::ScrollRight => scroll_by(app, 1, 0),
+        Action::PageDown => scroll_page(app, 1),
+        Action::PageUp => scroll_page(app, -1),
+        Action::Top => {
+            app.call_on_name("page_scroll", |scroll: &mut PageScrollView| scroll.scroll_to_top());
+        },
+        Action::Bottom => {
+            app.call_on_name("page_scroll", |scroll: &mut PageScrollView| {
+                scroll.scroll_to_bottom()
+            });
+        },
+        Action::CommandLine => open_command_line(app),
+        Action::Theme => theme_dialog(app),
+        Action::Quit => quit_dialog(app),
+    }
+}
+
+fn scroll_by(app: &mut Cursive, dx: isize, dy: isize) {
+    app.call_on_name("page_scroll", |scroll: &mut PageScrollView| {
+        let offset = scroll.content_viewport().top_left();
+        let x = (offset.x as isize + dx).max(0) as usize;
+        let y = (offset.y as isize + dy).max(0) as usize;
+        scroll.set_offset(Vec2::new(x, y));
+    });
+}
+
+// Scrolls by whole pages, leaving a row of the old page in view.
+fn scroll_page(app: &mut Cursive, pages: isize) {
+    let height = app.call_on_name("page_scroll", |scroll: &mut PageScrollView| {
+        scroll.content_viewport().height()
+    });
+    let height = height.unwrap_or(1).saturating_sub(1).max(1) as isize;
+    scroll_by(app, 0, pages * height);
+}
+
+// What fetching a URL came to.
+enum Fetched {
+    // The page as gemtext, the direction suggested by its lang parameter and
+    // what the status bar shows about it.
+    Page(Vec<GemtextToken>, Option<Direction>, Option<PageInfo>, Source),
+    // An image to show in the page.
+    Image(Rc<RgbaImage>, PageInfo, Source),
+    // A response that isn't text, to be opened with another program or
+    // saved rather than shown.
+    Unhandled(Box<ResponseStream>),
+    // The server's certificate has changed since it was first seen. Holds
+    // the host and the new fingerprint; nothing was sent to the server.
+    Changed(String, String),
+}
+
+// Fetches url and turns the response into gemtext, unless it's something
+// that can't be shown. The client certificate configured for url is sent
+// along, and if it can't be loaded the request isn't made at all rather than
+// made without it.
+fn fetch(browser: &mut Browser, url: &Url) -> Fetched {
+    let started = Instant::now();
+    let identity = browser.identity(url);
+    let identity_name = identity.as_ref().ok().and_then(|i| i.as_ref()).map(|i| i.name.clone());
+    let host = host_key(url);
+    let opened = match identity {
+        Ok(identity) => open(url, identity.as_ref(), &browser.known_hosts, &browser.about_sources()),
+        Err(error) => Err(OpenError::new(&error.to_string())),
+    };
+    if let Some(fingerprint) = opened.as_ref().err().and_then(|error| error.changed.clone()) {
+        return Fetched::Changed(host, fingerprint);
+    }
+    let trust = opened.as_ref().ok()
+        .and_then(|stream| stream.fingerprint.as_ref())
+        .map(|fingerprint| browser.known_hosts.check(&host, fingerprint));
+    let transaction = match opened {
+        Ok(stream) if (20..=29).contains(&stream.response.status)
+            && !stream.response.is_text() => {
+            let inline = browser.settings.display.inline_images
+                && is_image(&stream.response.mimetype);
+            if !inline {
+                return Fetched::Unhandled(Box::new(stream));
+            }
+            stream.finish()
+        },
+        Ok(stream) => stream.finish(),
+        Err(error) => Transaction {
+            response: create_fake_response(20, &error.to_string()),
+            fingerprint: None,
+            elapsed: started.elapsed(),
+        },
+    };
+
+    page_from(transaction, trust, identity_name)
+}
+
+// Turns a finished transaction into what the tab shows, whether it came
+// from a server or from about:.
+fn page_from(transaction: Transaction, trust: Option<TrustState>, identity: Option<String>)
+    -> Fetched {
+    let response = transaction.response;
+    let source = Source {
+        header: format!("{} {}", response.status, response.meta).trim_end().to_owned(),
+        body: response.data.clone(),
+    };
+    let info = PageInfo {
+        status: response.status,
+        meta: response.meta.clone(),
+        mimetype: response.mimetype.clone(),
+        charset: response.charset.clone(),
+        size: response.data.len(),
+        elapsed: transaction.elapsed,
+        trust,
+        identity,
+    };
+    if (20..=29).contains(&response.status) && is_image(&response.mimetype) {
+        return match decode_image(&response.data) {
+            Ok(image) => Fetched::Image(Rc::new(image), info, source),
+            Err(error) => Fetched::Page(parse_gemtext(&error), None, Some(info), source),
+        };
+    }
+    let chain = parse_body(&response.mimetype, &response.body);
+
+    let direction_hint = if response.lang.is_empty() {
+        None
+    } else {
+        Some(direction_for_lang(&response.lang))
+    };
+    Fetched::Page(chain, direction_hint, Some(info), source)
+}
+
+// How loading a page moves through the tab's back and forward lists.
+#[derive(Copy, Clone, PartialEq)]
+enum Navigation {
+    New,
+    Back,
+    Forward,
+    Reload,
+}
+
+fn navigate(app: &mut Cursive, url: Url, navigation: Navigation) {
+    match (url.scheme(), url.path(), url.query()) {
+        ("gemini", _, _) => {},
+        // Links on the downloads page act on a download, then show the page
+        // again. They're only acted on when followed from the downloads page
+        // itself, so a link on a capsule can't delete or open files.
+        ("about", "downloads", Some(query)) => {
+            let showing = app.user_data::<Browser>()
+                .and_then(|browser| browser.tab().url.as_ref().map(|url| url.as_str() == DOWNLOADS_URL))
+                .unwrap_or(false);
+            if showing {
+                if let Err(error) = downloads_action(app, query) {
+                    app.add_layer(Dialog::info(error));
+                }
+            }
+            let navigation = if showing { Navigation::Reload } else { Navigation::New };
+            if let Ok(url) = Url::parse(DOWNLOADS_URL) {
+                navigate(app, url, navigation);
+            }
+            return;
+        },
+        ("about", _, _) => {},
+        (scheme, _, _) => {
+            app.add_layer(Dialog::info(format!("Can't open {} links yet.", scheme)));
+            return;
+        },
+    }
+    remember_scroll(app);
+    let fetched = match app.user_data::<Browser>() {
+        Some(browser) => fetch(browser, &url),
+        None => return,
+    };
+    let fetched = match fetched {
+        Fetched::Unhandled(stream) => {
+            let placeholder = format!("{} isn't a page. Reload it to open it again.", url);
+            settle_restored_tab(app, &url, &placeholder);
+            open_with_dialog(app, url, *stream);
+            return;
+        },
+        Fetched::Changed(host, fingerprint) => {
+            let placeholder = format!("The certificate of {} has changed. Reload it to check it again.", host);
+            settle_restored_tab(app, &url, &placeholder);
+            certificate_changed_dialog(app, url, navigation, host, fingerprint);
+            return;
+        },
+        fetched => fetched,
+    };
+    if let Some(browser) = app.user_data::<Browser>() {
+        if url.scheme() == "gemini" {
+            browser.history.add(&url);
+        }
+        let tab = browser.tab_mut();
+        // Reloading keeps the reader where they were.
+        let scroll = tab.scroll;
+        if let Some(previous) = tab.url.take() {
+            match navigation {
+                Navigation::New => {
+                    tab.back.push(previous);
+                    tab.forward.clear();
+                },
+                Navigation::Back => tab.forward.push(previous),
+                Navigation::Forward => tab.back.push(previous),
+                Navigation::Reload => {},
+            }
+        }
+        match fetched {
+            Fetched::Page(chain, direction_hint, info, source) => {
+                tab.set_page(url, chain, direction_hint);
+                tab.info = info;
+                tab.source = source;
+            },
+            Fetched::Image(image, info, source) => {
+                tab.set_image(url, image);
+                tab.info = Some(info);
+                tab.source = source;
+            },
+            Fetched::Unhandled(_) | Fetched::Changed(_, _) => {},
+        }
+        if navigation == Navigation::Reload {
+            tab.scroll = scroll;
+        }
+    }
+    show_tab(app);
+}
+
+// Gives a restored tab whose page couldn't be shown a page of its own
+// saying so, so switching back to it doesn't ask about it again.
+fn settle_restored_tab(app: &mut Cursive, url: &Url, message: &str) {
+    let restored = app.user_data::<Browser>().is_some_and(|browser| {
+        let tab = browser.tab_mut();
+        if !tab.restored {
+            return false;
+        }
+        let title = tab.title.clone();
+        tab.set_page(url.clone(), parse_gemtext(&format!("{}\n", message)), None);
+        tab.title = title;
+        true
+    });
+    if restored {
+        show_tab(app);
+    }
+}
+
+// Asks whether to trust a server whose certificate has changed since it was
+// first seen, and loads url again with the new certificate if so.
+fn certificate_changed_dialog(app: &mut Cursive, url: Url, navigation: Navigation, host: String,
+                              fingerprint: String) {
+    let message = format!("The certificate of {} has changed since it was first seen. \
+                           Servers change certificates when they renew them, but it could \
+                           also be someone else answering for the server.\n\n\
+                           New fingerprint: {}\n\nTrust the new certificate?", host, fingerprint);
+    app.add_layer(Dialog::text(message)
+        .title("Certificate changed")
+        .button("Trust", move |s| {
+            s.pop_layer();
+            if let Some(browser) = s.user_data::<Browser>() {
+                browser.known_hosts.trust(&host, &fingerprint);
+            }
+            navigate(s, url.clone(), navigation);
+        })
+        .dismiss_button("Cancel")
+        .max_width(72));
+}
+
+// Opens url in the current tab.
+pub fn open_url(app: &mut Cursive, url: Url) {
+    navigate(app, url, Navigation::New);
+}
+
+fn go_back(app: &mut Cursive) {
+    let url = app.user_data::<Browser>().and_then(|browser| browser.tab_mut().back.pop());
+    if let Some(url) = url {
+        navigate(app, url, Navigation::Back);
+    }
+}
+
+fn go_forward(app: &mut Cursive) {
+    let url = app.user_data::<Browser>().and_then(|browser| browser.tab_mut().forward.pop());
+    if let Some(url) = url {
+        navigate(app, url, Navigation::Forward);
+    }
+}
+
+pub fn reload(app: &mut Cursive) {
+    let url = app.user_data::<Browser>().and_then(|browser| browser.tab().url.clone());
+    if let Some(url) = url {
+        navigate(app, url, Navigation::Reload);
+    }
+}
+
+pub fn bookmark_page(app: &mut Cursive) {
+    let page = app.user_data::<Browser>()
+        .and_then(|browser| browser.tab().url.clone().map(|url| (url, browser.tab().title.clone())));
+    if let Some((url, title)) = page {
+        bookmark_url(app, &url, &title);
+    }
+}
+
+fn bookmark_url(app: &mut Cursive, url: &Url, title: &str) {
+    let name = if title.is_empty() { url.to_string() } else { title.to_owned() };
+    let added = match app.user_data::<Browser>() {
+        Some(browser) => browser.bookmarks.add(url, title),
+        None => return,
+    };
+    let message = if added {
+        format!("Bookmarked {}", name)
+    } else {
+        format!("{} is already bookmarked", name)
+    };
+    app.add_layer(Dialog::info(message));
+}
+
+pub fn open_in_new_tab(app: &mut Cursive, url: Url) {
+    remember_scroll(app);
+    if let Some(browser) = app.user_data::<Browser>() {
+        browser.open_tab();
+    }
+    open_url(app, url);
+}
+
+// Switches to the tab offset tabs away from the current one, wrapping
+// around at either end.
+pub fn cycle_tab(app: &mut Cursive, offset: isize) {
+    remember_scroll(app);
+    if let Some(browser) = app.user_data::<Browser>() {
+        let count = browser.tabs.len() as isize;
+        browser.current_tab = (browser.current_tab as isize + offset).rem_euclid(count) as usize;
+    }
+    show_tab(app);
+}
+
+// Puts the current tab's page into the page view, scrolled to where it was
+// left, and redraws the tab bar. A tab restored from the last session has
+// its page fetched first.
+pub fn show_tab(app: &mut Cursive) {
+    let restored = app.user_data::<Browser>().is_some_and(|browser| browser.tab().restored);
+    if restored {
+        reload(app);
+        return;
+    }
+    let (chain, links, direction_hint, image, scroll, tab_bar) = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let (chain, links, direction_hint, image) = tab_content(browser);
+            let tab_bar = (browser.tabs.iter().map(|tab| tab.title.clone()).collect::<Vec<_>>(),
+                           browser.current_tab);
+            (chain, links, direction_hint, image, browser.tab().scroll, tab_bar)
+        },
+        None => return,
+    };
+
+    app.call_on_name("page", |page: &mut PageView| {
+        page.set_content(chain, links);
+        page.set_direction_hint(direction_hint);
+        page.set_image(image);
+    });
+    app.call_on_name("page_scroll", |scroll: &mut PageScrollView| {
+        scroll.scroll_to_top();
+        scroll.scroll_to_left();
+    });
+    // The page can't be scrolled down until it's been laid out again.
+    if scroll > 0 {
+        let _ = app.cb_sink().send(Box::new(move |s| scroll_to_row(s, scroll)));
+    }
+    app.call_on_name("tab_bar", |bar: &mut TabBar| bar.set_tabs(&tab_bar.0, tab_bar.1));
+    show_address(app);
+    update_status(app);
+}
+
+// What the page view shows for the current tab: its page, or its source.
+fn tab_content(browser: &Browser)
+    -> (Vec<GemtextToken>, Vec<PageLink>, Option<Direction>, Option<Rc<RgbaImage>>) {
+    let tab = browser.tab();
+    if tab.view_source {
+        return (parse_body("text/plain", &tab.source.text()), Vec::new(), None, None);
+    }
+    let history = &browser.history;
+    let links = page_links(&tab.chain, tab.url.as_ref(), |link| history.is_visited(link));
+    (tab.chain.clone(), links, tab.direction_hint, tab.image.clone())
+}
+
+// Shows the current tab's page again after it's changed, leaving the scroll
+// position, search and selected link where they are.
+pub fn refresh_page(app: &mut Cursive) {
+    let (chain, links) = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let (chain, links, _, _) = tab_content(browser);
+            (chain, links)
+        },
+        None => return,
+    };
+    app.call_on_name("page", |page: &mut PageView| page.update_content(chain, links));
+}
+
+// Offers what can be done with a right clicked link.
+fn link_menu(app: &mut Cursive, url: Url, text: &str) {
+    let mut select = SelectView::new();
+    select.add_item("Open", 0);
+    select.add_item("Open in new tab", 1);
+    select.add_item("Copy URL", 2);
+    select.add_item("Bookmark", 3);
+    select.add_item("Download", 4);
+    let title = text.to_owned();
+    let link = url.clone();
+    select.set_on_submit(move |s: &mut Cursive, choice: &usize| {
+        s.pop_layer();
+        let url = link.clone();
+        let result = match choice {
+            0 => {
+                open_url(s, url);
+                Ok(())
+            },
+            1 => {
+                open_in_new_tab(s, url);
+                Ok(())
+            },
+            2 => copy_to_clipboard(url.as_str()),
+            3 => {
+                bookmark_url(s, &url, &title);
+                Ok(())
+            },
+            _ => {
+                save_as_dialog(s, url);
+                Ok(())
+            },
+        };
+        if let Err(error) = result {
+            s.add_layer(Dialog::info(error));
+        }
+    });
 
     app.add_layer(
         OnEventView::new(
-            Dialog::around(layout)
-            .title("Enter URL")
-            .button("Visit", |t: &mut Cursive| {
-                let url = t.call_on_name("urlbox", |view: &mut EditView| {
-                    view.get_content()
-                }).unwrap();
-                update_tab(t, &url);
-            })
+            Dialog::around(select)
+            .title(url.to_string())
+            .dismiss_button("Cancel"))
+        .on_event(event::Key::Esc, |s| {
+            s.pop_layer();
+        }));
+}
+
+// The line below the page with the URL on the left and what the server sent
+// on the right. While a link is selected its target replaces the URL.
+fn status_bar() -> PaddedView<LinearLayout> {
+    PaddedView::new(
+        Margins::lr(1, 1),
+        LinearLayout::horizontal()
+        .child(TextView::new("").no_wrap().with_name("status_url").full_width())
+        .child(TextView::new("").no_wrap().with_name("status_info"))
+    )
+}
+
+// Shows where the page or selected link is, and how the page and any
+// running downloads are getting on.
+pub fn update_status(app: &mut Cursive) {
+    let selected = app
+        .call_on_name("page", |page: &mut PageView| page.selected_url().cloned())
+        .flatten();
+    let (url, info) = match app.user_data::<Browser>() {
+        Some(browser) => {
+            let tab = browser.tab();
+            let url = match selected {
+                Some(selected) => format!("→ {}", selected),
+                None => tab.url.as_ref().map(|url| url.to_string()).unwrap_or_default(),
+            };
+            let mut info = tab.info.as_ref().map(|info| info.summary()).unwrap_or_default();
+            let (count, received) = browser.downloads.in_progress();
+            if count > 0 {
+                let downloads = format!("↓ {} downloading, {}", count, format_size(received as usize));
+                info = if info.is_empty() { downloads } else { format!("{} · {}", downloads, info) };
+            }
+            let info = if info.is_empty() { info } else { format!("  {}", info) };
+            (url, info)
+        },
+        None => return,
+    };
+    app.call_on_name("status_url", |view: &mut TextView| view.set_content(url));
+    app.call_on_name("status_info", |view: &mut TextView| {
+        view.set_content(info)
+    });
+}
+
+// Moves the link cursor and keeps the link it lands on in view.
+fn select_link(app: &mut Cursive, forward: bool) {
+    let row = app.call_on_name("page", |page: &mut PageView| page.select_link(forward));
+    if let Some(row) = row.flatten() {
+        scroll_into_view(app, Vec2::new(0, row), 0);
+    }
+    update_status(app);
+}
+
+fn follow_link(app: &mut Cursive) {
+    let url = app.call_on_name("page", |page: &mut PageView| page.selected_url().cloned());
+    if let Some(url) = url.flatten() {
+        open_url(app, url);
+    }
+}
+
+// Labels the visible links, and follows whichever one gets typed.
+fn start_hints(app: &mut Cursive, new_tab: bool) {
+    app.call_on_name("page", |page: &mut PageView| page.start_hints(new_tab));
+}
+
+// The line below the page that searches are typed into, hidden until '/'.
+fn search_bar() -> NamedView<HideableView<LinearLayout>> {
+    let search_box = OnEventView::new(
+        EditView::new()
+            .on_edit(|s, query, _| search(s, query))
+            .on_submit(|s, _| close_search(s, false))
+            .with_name("search_box"))
+        .on_event(event::Key::Esc, |s| close_search(s, true))
+        .on_event(event::Event::CtrlChar('t'), |s| {
+            toggle_search_option(s, |options| options.case_sensitive ^= true)
+        })
+        .on_event(event::Event::CtrlChar('r'), |s| {
+            toggle_search_option(s, |options| options.regex ^= true)
+        })
+        .on_event_inner(EventTrigger::any(), |_, event| swallow_keys(event));
+
+    HideableView::new(
+        LinearLayout::horizontal()
+        .child(TextView::new(" /"))
+        .child(search_box.full_width())
+        .child(TextView::new("").with_name("search_status"))
+    )
+    .hidden()
+    .with_name("search_bar")
+}
+
+fn open_search(app: &mut Cursive) {
+    app.call_on_name("search_box", |view: &mut EditView| view.set_content(""));
+    app.call_on_name("search_status", |view: &mut TextView| view.set_content(""));
+    app.call_on_name("search_bar", |view: &mut HideableView<LinearLayout>| {
+        view.unhide()
+    });
+    let _ = app.focus_name("search_box");
+}
+
+// Hides the search bar. Cancelling also drops the highlighted matches.
+fn close_search(app: &mut Cursive, cancel: bool) {
+    if cancel {
+        app.call_on_name("page", |page: &mut PageView| page.clear_search());
+    }
+    app.call_on_name("search_bar", |view: &mut HideableView<LinearLayout>| {
+        view.hide()
+    });
+    let _ = app.focus_name("page");
+}
+
+// Searches again as the query is typed.
+fn search(app: &mut Cursive, query: &str) {
+    let options = match app.user_data::<Browser>() {
+        Some(browser) => browser.settings.search.clone(),
+        None => return,
+    };
+    let result = app.call_on_name("page", |page: &mut PageView| page.search(query, &options));
+    let status = match result {
+        Some(Ok(_)) => {
+            scroll_to_match(app);
+            match_status(app)
+        },
+        Some(Err(error)) => error.to_string(),
+        None => String::new(),
+    };
+    let flags = format!(
+        "{}{} ",
+        if options.case_sensitive { " [Aa]" } else { "" },
+        if options.regex { " [.*]" } else { "" },
+    );
+    app.call_on_name("search_status", |view: &mut TextView| {
+        view.set_content(format!("{}{}", status, flags))
+    });
+}
+
+fn toggle_search_option<F>(app: &mut Cursive, toggle: F)
+where
+    F: Fn(&mut SearchSettings),
+{
+    if let Some(browser) = app.user_data::<Browser>() {
+        toggle(&mut browser.settings.search);
+    }
+    let query = app.call_on_name("search_box", |view: &mut EditView| view.get_content());
+    if let Some(query) = query {
+        search(app, &query);
+    }
+}
+
+fn match_status(app: &mut Cursive) -> String {
+    let count = app.call_on_name("page", |page: &mut PageView| page.match_count());
+    match count.flatten() {
+        Some((_, 0)) => "No matches".to_owned(),
+        Some((current, total)) => format!("{} of {}", current, total),
+        None => String::new(),
+    }
+}
+
+fn jump_to_match(app: &mut Cursive, forward: bool) {
+    app.call_on_name("page", |page: &mut PageView| page.next_match(forward));
+    scroll_to_match(app);
+}
+
+// Scrolls the page just enough to show the current match, placing it a few
+// rows from the edge it came in from.
+fn scroll_to_match(app: &mut Cursive) {
+    let position = app.call_on_name("page", |page: &mut PageView| {
+        page.current_match_position()
+    });
+    if let Some(position) = position.flatten() {
+        scroll_into_view(app, position, SEARCH_CONTEXT_ROWS);
+    }
+}
+
+// Scrolls the page just enough to show position, with context rows kept
+// between it and the edge of the page where possible.
+fn scroll_into_view(app: &mut Cursive, position: Vec2, context: usize) {
+    app.call_on_name("page_scroll", |scroll: &mut PageScrollView| {
+        let viewport = scroll.content_viewport();
+        let mut offset = viewport.top_left();
+        let context = context.min(viewport.height() / 2);
+        if position.y < viewport.top() + context {
+            offset.y = position.y.saturating_sub(context);
+        } else if position.y + context > viewport.bottom() {
+            offset.y = position.y + context + 1 - viewport.height();
+        }
+        if position.x < viewport.left() || position.x > viewport.right() {
+            offset.x = position.x.saturating_sub(viewport.width() / 2);
+        }
+        scroll.set_offset(Vec2::new(offset.x, offset.y));
+    });
+}
+
+// Scrolls so row is at the top of the page, as far as the page allows.
+fn scroll_to_row(app: &mut Cursive, row: usize) {
+    app.call_on_name("page_scroll", |scroll: &mut PageScrollView| {
+        scroll.set_offset(Vec2::new(0, row));
+    });
+}
+
+fn jump_to_heading(app: &mut Cursive, forward: bool) {
+    let row = app.call_on_name("page", |page: &mut PageView| page.heading_row(forward));
+    if let Some(row) = row.flatten() {
+        scroll_to_row(app, row);
+    }
+}
+
+// Lists the page's headings, indented by level, and scrolls to whichever is
+// picked. The section currently being read is selected to begin with.
+fn outline_dialog(app: &mut Cursive) {
+    let headings = app.call_on_name("page", |page: &mut PageView| {
+        (page.outline(), page.current_heading())
+    });
+    let (entries, current) = match headings {
+        Some((entries, _)) if entries.is_empty() => {
+            app.add_layer(Dialog::info("This page has no headings."));
+            return;
+        },
+        Some(headings) => headings,
+        None => return,
+    };
+
+    let mut select = SelectView::new();
+    for entry in &entries {
+        let indent = "  ".repeat(entry.level - 1);
+        select.add_item(format!("{}{}", indent, entry.title), entry.token);
+    }
+    if let Some(i) = current.and_then(|c| entries.iter().position(|e| *e == c)) {
+        select.set_selection(i);
+    }
+    select.set_on_submit(|s: &mut Cursive, token: &usize| {
+        s.pop_layer();
+        let row = s.call_on_name("page", |page: &mut PageView| page.row_for_token(*token));
+        if let Some(row) = row.flatten() {
+            scroll_to_row(s, row);
+        }
+    });
+
+    app.add_layer(
+        OnEventView::new(
+            Dialog::around(select.scrollable())
+            .title("Contents")
+            .dismiss_button("Cancel"))
+        .on_event(event::Key::Esc, |s| {
+            s.pop_layer();
+        })
+        .on_event('o', |s| {
+            s.pop_layer();
+        }));
+}
+
+// Switches the whole interface, page included, over to another theme.
+pub fn apply_theme(app: &mut Cursive, theme: Theme) {
+    app.set_theme(to_cursive_theme(&theme));
+    app.call_on_name("page", |page: &mut PageView| page.set_theme(theme));
+}
+
+fn theme_dialog(app: &mut Cursive) {
+    let current = app.user_data::<Browser>()
+        .map(|browser| browser.settings.theme.name.clone())
+        .unwrap_or_default();
+    let mut select = SelectView::new();
+    for name in BUILTIN_THEMES.iter() {
+        select.add_item_str(*name);
+    }
+    if let Some(i) = BUILTIN_THEMES.iter().position(|name| *name == current) {
+        select.set_selection(i);
+    }
+    select.set_on_submit(|s: &mut Cursive, name: &str| {
+        s.pop_layer();
+        // Keep the user's [theme.styles] overrides when switching.
+        let theme_settings = match s.user_data::<Browser>() {
+            Some(browser) => {
+                browser.settings.theme.name = name.to_owned();
+                browser.settings.theme.clone()
+            },
+            None => ThemeSettings::default(),
+        };
+        let theme = load_theme(&theme_settings)
+            .unwrap_or_else(|_| Theme::builtin(name).unwrap_or_default());
+        apply_theme(s, theme);
+    });
+
+    app.add_layer(
+        OnEventView::new(
+            Dialog::around(select)
+            .title("Theme")
             .dismiss_button("Cancel"))
         .on_event(event::Key::Esc, |s| {
             s.pop_layer();
@@ -132,12 +1054,3 @@ fn quit_dialog(app: &mut Cursive) {
         .on_event(event::Key::Esc, |s| { s.pop_layer(); })
     );
 }
-
-// Helper funcs
-fn styled_string_from_token_chain(chain: &Vec<GemtextToken>) -> StyledString {
-    let mut styled_page_text = StyledString::new();
-    for token in chain {
-        styled_page_text.append(token.styled_string());
-    }
-    styled_page_text
-}